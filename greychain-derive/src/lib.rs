@@ -0,0 +1,148 @@
+#![allow(clippy::needless_return)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, format_ident};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, Fields, Index, Lit, LitStr, Path};
+
+/// module of the `Serializer` trait if there is no `#[serializer(crate = "...")]`
+const DEFAULT_PATH: &str = "crate::net::serialize";
+
+/// implements `Serializer` by encoding all fields in declaration order
+/// (field-less enums are encoded as the discriminant of the variant in one byte),
+/// `#[serializer(crate = "path")]` names the module of the trait if it is not `crate::net::serialize`
+#[proc_macro_derive(Serializer, attributes(serializer))]
+pub fn derive_serializer(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let result = parse_path(&input).and_then(|path| match &input.data {
+        Data::Struct(data) => Ok(derive_struct(&input, &data.fields, &path)),
+        Data::Enum(data) => derive_enum(&input, data, &path),
+        Data::Union(_) => Err(syn::Error::new_spanned(&input.ident, "Serializer can not be derived for unions")),
+    });
+
+    return match result {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    };
+}
+
+fn parse_path(input: &DeriveInput) -> syn::Result<Path> {
+    let mut path = syn::parse_str::<Path>(DEFAULT_PATH)?;
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("serializer")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("crate") {
+                return Err(meta.error("unknown serializer attribute"));
+            }
+
+            path = meta.value()?.parse::<LitStr>()?.parse::<Path>()?;
+            return Ok(());
+        })?;
+    }
+
+    return Ok(path);
+}
+
+fn derive_struct(input: &DeriveInput, fields: &Fields, path: &Path) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let members = fields.iter().enumerate().map(|(i, field)| match &field.ident {
+        Some(ident) => quote!(#ident),
+        None => { let index = Index::from(i); quote!(#index) }
+    }).collect::<Vec<_>>();
+    let vars = (0..members.len()).map(|i| format_ident!("field{}", i)).collect::<Vec<_>>();
+    let types = fields.iter().map(|field| &field.ty);
+
+    let construct = match fields {
+        Fields::Named(_) => quote!(#name { #(#members: #vars),* }),
+        Fields::Unnamed(_) => quote!(#name ( #(#vars),* )),
+        Fields::Unit => quote!(#name),
+    };
+
+    return quote! {
+        impl #impl_generics #path::Serializer for #name #ty_generics #where_clause {
+            fn serialize(&self, dst: &mut [u8]) -> usize {
+                let mut start: usize = 0;
+                #(start += #path::Serializer::serialize(&self.#members, &mut dst[start..]);)*
+                return start;
+            }
+
+            fn deserialize(bytes: &[u8]) -> Result<(usize, Self), #path::DecodeError> {
+                let mut start: usize = 0;
+                #(
+                    let (size, #vars) = <#types as #path::Serializer>::deserialize(&bytes[start..])?;
+                    start += size;
+                )*
+                return Ok((start, #construct));
+            }
+
+            #[allow(unused_mut)]
+            fn get_size(&self) -> usize {
+                let mut size: usize = 0;
+                #(size += #path::Serializer::get_size(&self.#members);)*
+                return size;
+            }
+        }
+    };
+}
+
+fn derive_enum(input: &DeriveInput, data: &syn::DataEnum, path: &Path) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    if let Some(variant) = data.variants.iter().find(|variant| !matches!(variant.fields, Fields::Unit)) {
+        return Err(syn::Error::new_spanned(variant, "Serializer can only be derived for field-less enums"));
+    }
+
+    let variants = data.variants.iter().map(|variant| &variant.ident).collect::<Vec<_>>();
+    let indices = gen_discriminants(data)?;
+    let unknown = LitStr::new(&format!("unknown {} variant", name), name.span());
+
+    return Ok(quote! {
+        impl #impl_generics #path::Serializer for #name #ty_generics #where_clause {
+            fn serialize(&self, dst: &mut [u8]) -> usize {
+                let index: u8 = match self { #(#name::#variants => #indices),* };
+                return #path::Serializer::serialize(&index, dst);
+            }
+
+            fn deserialize(bytes: &[u8]) -> Result<(usize, Self), #path::DecodeError> {
+                let (size, index) = <u8 as #path::Serializer>::deserialize(bytes)?;
+                return match index {
+                    #(#indices => Ok((size, #name::#variants)),)*
+                    _ => Err(#path::DecodeError::Invalid(#unknown)),
+                };
+            }
+
+            fn get_size(&self) -> usize {
+                return 1;
+            }
+        }
+    });
+}
+
+/// discriminants the way rustc assigns them (explicit ones or one more than the previous variant),
+/// they have to be integer literals that fit into a byte
+fn gen_discriminants(data: &syn::DataEnum) -> syn::Result<Vec<u8>> {
+    let mut discriminants = Vec::<u8>::new();
+    let mut next: Option<u8> = Some(0);
+
+    for variant in &data.variants {
+        let discriminant = match &variant.discriminant {
+            Some((_, Expr::Lit(ExprLit { lit: Lit::Int(lit), .. }))) => lit.base10_parse::<u8>()
+                .map_err(|_| syn::Error::new_spanned(lit, "Serializer needs discriminants that fit into a byte"))?,
+            Some((_, expr)) => return Err(syn::Error::new_spanned(expr, "Serializer needs integer literals as discriminants")),
+            None => next.ok_or_else(|| syn::Error::new_spanned(variant, "Serializer needs discriminants that fit into a byte"))?,
+        };
+
+        if discriminants.contains(&discriminant) {
+            return Err(syn::Error::new_spanned(variant, "Serializer needs distinct discriminants"));
+        }
+
+        discriminants.push(discriminant);
+        next = discriminant.checked_add(1);
+    }
+
+    return Ok(discriminants);
+}
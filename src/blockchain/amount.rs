@@ -0,0 +1,139 @@
+use std::{fmt::{Display, Debug}, str::FromStr};
+
+use crate::net::serialize::Serializer;
+
+/// coin amount in base units (1 GRY = 10^8 units)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serializer)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const DECIMALS: usize = 8;
+    pub const UNITS_PER_GRY: u64 = 100_000_000;
+
+    pub const fn from_units(units: u64) -> Amount {
+        return Amount(units);
+    }
+
+    pub const fn from_gry(gry: u64) -> Amount {
+        return Amount(gry * Amount::UNITS_PER_GRY);
+    }
+
+    pub const fn units(&self) -> u64 {
+        return self.0;
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        return self.0.checked_add(other.0).map(Amount);
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        return self.0.checked_sub(other.0).map(Amount);
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gry = self.0 / Amount::UNITS_PER_GRY;
+        let frac = self.0 % Amount::UNITS_PER_GRY;
+
+        if frac == 0 {
+            return write!(f, "{} GRY", gry);
+        }
+
+        let frac = format!("{:0width$}", frac, width = Amount::DECIMALS);
+        return write!(f, "{}.{} GRY", gry, frac.trim_end_matches('0'));
+    }
+}
+
+impl Debug for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return Display::fmt(self, f);
+    }
+}
+
+/// parses the decimal form ("1.5 GRY" or just "1.5")
+impl FromStr for Amount {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_suffix("GRY").unwrap_or(s).trim_end();
+
+        let (gry, frac) = s.split_once('.').unwrap_or((s, ""));
+        if gry.is_empty() && frac.is_empty() {
+            return Err("empty amount");
+        }
+
+        if !gry.bytes().all(|b| b.is_ascii_digit()) || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("invalid digit in amount");
+        }
+
+        if frac.len() > Amount::DECIMALS {
+            return Err("too many decimal places");
+        }
+
+        let gry = if gry.is_empty() { 0 } else { gry.parse::<u64>().map_err(|_| "amount is too large")? };
+        let frac = format!("{:0<width$}", frac, width = Amount::DECIMALS).parse::<u64>().unwrap();
+
+        return gry.checked_mul(Amount::UNITS_PER_GRY)
+            .and_then(|units| units.checked_add(frac))
+            .map(Amount)
+            .ok_or("amount is too large");
+    }
+}
+
+/// chain specs write amounts in the decimal form (like "50 GRY")
+impl serde::Serialize for Amount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.collect_str(self);
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        return <String as serde::Deserialize>::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Amount;
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!("1.5 GRY".parse::<Amount>(), Ok(Amount::from_units(150_000_000)));
+        assert_eq!("0.00000001".parse::<Amount>(), Ok(Amount::from_units(1)));
+        assert_eq!("42".parse::<Amount>(), Ok(Amount::from_gry(42)));
+        assert_eq!(".5GRY".parse::<Amount>(), Ok(Amount::from_units(50_000_000)));
+
+        assert!("".parse::<Amount>().is_err());
+        assert!("-1".parse::<Amount>().is_err());
+        assert!("1.000000001".parse::<Amount>().is_err());
+        assert!("1e3".parse::<Amount>().is_err());
+        assert!("184467440738 GRY".parse::<Amount>().is_err());
+
+        assert_eq!(Amount::from_units(150_000_000).to_string(), "1.5 GRY");
+        assert_eq!(Amount::from_units(1).to_string(), "0.00000001 GRY");
+        assert_eq!(Amount::from_gry(50).to_string(), "50 GRY");
+
+        for s in ["1.5 GRY", "0.00000001 GRY", "50 GRY", "0 GRY"] {
+            assert_eq!(s.parse::<Amount>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let a = Amount::from_gry(3);
+        let b = Amount::from_gry(2);
+
+        assert_eq!(a.checked_sub(b), Some(Amount::from_gry(1)));
+        assert_eq!(b.checked_sub(a), None);
+        assert_eq!(a.checked_add(b), Some(Amount::from_gry(5)));
+        assert_eq!(Amount::from_units(u64::MAX).checked_add(Amount::from_units(1)), None);
+    }
+}
@@ -1,121 +1,175 @@
-use std::{fmt::Display, hash::{Hash, Hasher}, collections::hash_map::DefaultHasher, time::{SystemTime, UNIX_EPOCH}};
+use std::{fmt::Display, time::{SystemTime, UNIX_EPOCH}, collections::HashSet};
 
-use crate::net::serialize::Serializer;
+use crate::{net::serialize::Serializer, crypto::Hash};
 
-use super::{Transaction, Miner};
+use super::{Transaction, BlockHeader, TxProof, Amount, DoubleSign, merkle::{merkle_root, MerkleProof}};
 
-#[derive(Clone)]
+const SEPARATOR: &str = "==========================";
+
+/// max txs per block (without coinbase)
+pub const MAX_BLOCK_TXS: usize = 100;
+/// max double sign evidence per block
+pub const MAX_BLOCK_EVIDENCE: usize = 10;
+
+#[derive(Clone, Serializer)]
 pub struct Block {
-    pub prev_hash: u64,
+    pub prev_hash: Hash,
     pub round: usize,
     pub timestamp: u128,
-    pub tx: Transaction,
-    nonce: u64,
-    solution: u64,
-    pub hash: u64,
+    /// target of the consensus engine (the proof of work hash has to be below it)
+    pub target: Hash,
+    pub coinbase: Transaction,
+    pub txs: Vec<Transaction>,
+    /// validators to slash (for proof of stake)
+    pub evidence: Vec<DoubleSign>,
+    pub merkle_root: Hash,
+    /// proof of the consensus engine (solution or signature of the authority)
+    seal: Vec<u8>,
+    pub hash: Hash,
 }
 
 impl Block {
-    pub fn new(tx: Transaction, prev_hash: u64, round: usize, nonce: u64, solution: u64) -> Block {
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros();
-        let hash = Self::gen_hash(&tx, prev_hash, round, timestamp, nonce, solution);
-        return Block { prev_hash, tx, hash, round, timestamp, nonce, solution };
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(txs: Vec<Transaction>, evidence: Vec<DoubleSign>, coinbase: Transaction, prev_hash: Hash, round: usize, timestamp: u128, target: Hash, seal: Vec<u8>) -> Block {
+        let merkle_root = Self::gen_merkle_root(&txs, &evidence, &coinbase);
+        let hash = Self::gen_hash(&Self::gen_header_hash(prev_hash, round, timestamp, target, merkle_root), &seal);
+        return Block { prev_hash, coinbase, txs, evidence, merkle_root, hash, round, timestamp, target, seal };
     }
 
-    pub fn rehash(&mut self, new_prev_hash: u64) {
-        self.prev_hash = new_prev_hash;
-        self.hash = Self::gen_hash(&self.tx, self.prev_hash, self.round, self.timestamp, self.nonce, self.solution);
+    /// first block of a chain (without parent and seal, every node builds it from the chain spec)
+    pub fn new_genesis(chain_id: &Hash, timestamp: u128, target: Hash) -> Block {
+        return Self::new(Vec::new(), Vec::new(), Transaction::new_genesis(chain_id), Hash::ZERO, 0, timestamp, target, Vec::new());
     }
 
-    pub fn get_minig_hash(&self) -> u64 {
-        return Miner::gen_mining_hash(self.nonce, self.solution);
+    pub fn gen_timestamp() -> u128 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros();
     }
 
-    fn gen_hash(tx: &Transaction, prev_hash: u64, round: usize, timestamp: u128, nonce: u64, solution: u64) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        prev_hash.hash(&mut hasher);
-        round.hash(&mut hasher);
-        timestamp.hash(&mut hasher);
-        tx.hash(&mut hasher);
-        nonce.hash(&mut hasher);
-        solution.hash(&mut hasher);
-
-        return hasher.finish();
+    pub fn get_header(&self) -> BlockHeader {
+        return BlockHeader {
+            prev_hash: self.prev_hash,
+            round: self.round,
+            timestamp: self.timestamp,
+            target: self.target,
+            merkle_root: self.merkle_root,
+            seal: self.seal.clone(),
+            hash: self.hash,
+        };
     }
-}
 
-impl Display for Block {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "{}\nhash: {} (prev)\nround: {}\ntimestamp: {}\n{}nonce: {}\nsolution: {}\nhash: {} (cur)\n{}\n",
-                      "==========================",
-                      self.prev_hash,
-                      self.round,
-                      self.timestamp,
-                      self.tx,
-                      self.nonce,
-                      self.solution,
-                      self.hash,
-                      "==========================");
+    /// checks if the header commits to the txs (and the evidence) of the block
+    pub fn verify_merkle_root(&self) -> bool {
+        return self.merkle_root == Self::gen_merkle_root(&self.txs, &self.evidence, &self.coinbase);
     }
-}
 
-impl Serializer for Block {
-    fn serialize(&self, dst: &mut [u8]) -> usize {
-        let mut start: usize = 0;
+    /// a block with its last tx (or evidence) twice has the same merkle root as the block with it once
+    /// (the last node of an odd level is paired with itself), so such blocks are never valid
+    pub fn has_duplicate_leaves(&self) -> bool {
+        let mut seen = HashSet::<Hash>::new();
+        return !Self::gen_leaves(&self.txs, &self.evidence, &self.coinbase).into_iter().all(|leaf| seen.insert(leaf));
+    }
 
-        start += self.prev_hash.serialize(&mut dst[start..]);
-        start += self.round.serialize(&mut dst[start..]);
-        start += self.timestamp.serialize(&mut dst[start..]);
-        start += self.tx.serialize(&mut dst[start..]);
-        start += self.nonce.serialize(&mut dst[start..]);
-        start += self.solution.serialize(&mut dst[start..]);
-        start += self.hash.serialize(&mut dst[start..]);
+    /// inclusion proof of a tx of this block (the coinbase is always the first leaf)
+    pub fn merkle_proof(&self, tx_id: Hash) -> Option<MerkleProof> {
+        let idx = self.txs.iter().position(|tx| tx.gen_hash() == tx_id)?;
+        return MerkleProof::new(&Self::gen_leaves(&self.txs, &self.evidence, &self.coinbase), idx+1);
+    }
 
-        return start;
+    /// inclusion proofs of all txs (coinbase included) paying or paid by `pub_key`
+    pub fn get_proofs_of(&self, pub_key: &str) -> Vec<TxProof> {
+        let leaves = Self::gen_leaves(&self.txs, &self.evidence, &self.coinbase);
+        return std::iter::once(&self.coinbase).chain(&self.txs).enumerate()
+            .filter(|(_, tx)| tx.payer == pub_key || tx.payee == pub_key)
+            .filter_map(|(i, tx)| Some(TxProof { tx: tx.clone(), block_hash: self.hash, proof: MerkleProof::new(&leaves, i)? }))
+            .collect();
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        let mut start: usize = 0;
+    pub fn verify_merkle_proof(&self, tx: &Transaction, proof: &MerkleProof) -> bool {
+        return proof.verify(&tx.gen_hash(), &self.merkle_root);
+    }
 
-        let (size, prev_hash) = u64::deserialize(&bytes[start..]);
-        start += size;
+    /// sum of the fees of the txs (None on overflow)
+    pub fn gen_fees(txs: &[Transaction]) -> Option<Amount> {
+        return txs.iter().try_fold(Amount::ZERO, |fees, tx| fees.checked_add(tx.fee));
+    }
 
-        let (size, round) = usize::deserialize(&bytes[start..]);
-        start += size;
+    /// commits the seal to the txs and the evidence of the block
+    pub fn gen_merkle_root(txs: &[Transaction], evidence: &[DoubleSign], coinbase: &Transaction) -> Hash {
+        return merkle_root(&Self::gen_leaves(txs, evidence, coinbase));
+    }
 
-        let (size, timestamp) = u128::deserialize(&bytes[start..]);
-        start += size;
+    /// coinbase first, then the txs and the evidence
+    fn gen_leaves(txs: &[Transaction], evidence: &[DoubleSign], coinbase: &Transaction) -> Vec<Hash> {
+        return std::iter::once(coinbase).chain(txs).map(|tx| tx.gen_hash())
+            .chain(evidence.iter().map(|evidence| evidence.gen_hash()))
+            .collect();
+    }
 
-        let (size, tx) = Transaction::deserialize(&bytes[start..]);
-        start += size;
+    /// everything the seal commits to
+    pub fn gen_header_hash(prev_hash: Hash, round: usize, timestamp: u128, target: Hash, merkle_root: Hash) -> Hash {
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend_from_slice(&prev_hash.0);
+        bytes.extend_from_slice(&(round as u64).to_le_bytes());
+        bytes.extend_from_slice(&timestamp.to_le_bytes());
+        bytes.extend_from_slice(&target.0);
+        bytes.extend_from_slice(&merkle_root.0);
 
-        let (size, nonce) = u64::deserialize(&bytes[start..]);
-        start += size;
+        return Hash::digest(&bytes);
+    }
 
-        let (size, solution) = u64::deserialize(&bytes[start..]);
-        start += size;
+    /// hash of the sealed block
+    pub fn gen_hash(header_hash: &Hash, seal: &[u8]) -> Hash {
+        let mut bytes = header_hash.0.to_vec();
+        bytes.extend_from_slice(seal);
 
-        let (size, hash) = u64::deserialize(&bytes[start..]);
-        start += size;
+        return Hash::digest(&bytes);
+    }
+}
 
-        return (start, Block{ prev_hash, round, timestamp, tx, nonce, solution, hash});
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{SEPARATOR}\nhash: {} (prev)\nround: {}\ntimestamp: {}\ntarget: {}\ncoinbase:\n{}txs: {}\n{}evidence: {}\nmerkle root: {}\nseal: {} bytes\nhash: {} (cur)\n{SEPARATOR}\n",
+                      self.prev_hash,
+                      self.round,
+                      self.timestamp,
+                      self.target,
+                      self.coinbase,
+                      self.txs.len(),
+                      self.txs.iter().map(|tx| tx.to_string() + "\n").collect::<String>(),
+                      self.evidence.len(),
+                      self.merkle_root,
+                      self.seal.len(),
+                      self.hash);
     }
 }
 
 impl PartialEq for Block {
     fn eq(&self, other: &Self) -> bool {
-        let a = Miner::gen_mining_hash(self.nonce, self.solution);
-        let b = Miner::gen_mining_hash(other.nonce, other.solution);
-
-        return a == b;
+        return self.hash == other.hash;
     }
 }
 
-impl PartialOrd for Block {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let a = Miner::gen_mining_hash(self.nonce, self.solution);
-        let b = Miner::gen_mining_hash(other.nonce, other.solution);
+#[cfg(test)]
+mod tests {
+    use crate::{blockchain::{Transaction, Amount}, crypto::Hash, test_utils::create_signer};
+
+    use super::Block;
+
+    #[test]
+    fn merkle_proofs_of_txs() {
+        let (pub_key_pem, sign_key) = create_signer();
+
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &pub_key_pem, 0, Amount::from_gry(50), &sign_key);
+        let txs = (1..4).map(|i| Transaction::new(&Hash::ZERO, &pub_key_pem, &"payee".to_string(), i, Amount::from_gry(i), Amount::ZERO, &sign_key)).collect::<Vec<Transaction>>();
+        let block = Block::new(txs.clone(), Vec::new(), coinbase.clone(), Hash::ZERO, 0, 0, Hash::ZERO, Vec::new());
+        assert!(block.verify_merkle_root());
+
+        for tx in &txs {
+            let proof = block.merkle_proof(tx.gen_hash()).unwrap();
+            assert!(block.verify_merkle_proof(tx, &proof));
+            assert!(!block.verify_merkle_proof(&coinbase, &proof));
+        }
 
-        return Some(a.cmp(&b));
+        assert!(block.merkle_proof(coinbase.gen_hash()).is_none());
     }
 }
@@ -1,116 +1,546 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::Path, sync::Arc};
 
-use super::Block;
+use crate::crypto::Hash;
+
+use super::{tree::ChainTree, Block, Ledger, RewardSchedule, Consensus, ChainSpec, DoubleSign, Transaction, MerkleProof, Amount, BlockStore, TxProof, MAX_BLOCK_TXS, MAX_BLOCK_EVIDENCE};
+
+/// first invalid block found by `Blockchain::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainError {
+    pub round: usize,
+    pub hash: Hash,
+    pub reason: &'static str,
+}
+
+impl Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "invalid block {} (round: {}): {}", self.hash, self.round, self.reason);
+    }
+}
 
 pub struct Blockchain {
-    blocks: Vec<Block>,
+    /// all known blocks and the main chain
+    tree: ChainTree<Block>,
+    reward: RewardSchedule,
+    consensus: Arc<dyn Consensus>,
+    /// id of the chain spec (txs are signed for it)
+    chain_id: Hash,
+    /// balances of the chain spec (before the first block after the genesis block)
+    genesis_ledger: Ledger,
+    ledger: Ledger,
+    store: Option<BlockStore>,
 }
 
 impl Blockchain {
-    pub fn new() -> Blockchain {
-        return Blockchain{ blocks: Vec::new() };
+    /// chain with only the genesis block of the spec
+    pub fn new(spec: &ChainSpec) -> Blockchain {
+        let consensus = spec.create_consensus();
+        let genesis = spec.gen_genesis(consensus.as_ref());
+        let genesis_ledger = Ledger::with_balances(&spec.get_balances()).expect("allocations have to fit into the balances");
+
+        return Blockchain{ tree: ChainTree::new(genesis), reward: spec.reward, consensus, chain_id: spec.gen_id(), ledger: genesis_ledger.clone(), genesis_ledger, store: None };
     }
 
-    pub fn add_block(&mut self, block: &Block) {
-        if block.round >= self.blocks.len() {
-            self.blocks.push(block.to_owned());
-        } else {
-            let round = block.round;
+    /// loads the blocks of the store (and appends every new block to it)
+    pub fn load(path: &Path, spec: &ChainSpec) -> Blockchain {
+        let mut blockchain = Blockchain::new(spec);
 
-            if self.verify(&block, round) {
-                // better block found
-                if block < &self.blocks[round] {
-                    println!("better block (round: {})", round);
+        let mut store = match BlockStore::open(path) {
+            Ok(store) => store,
+            Err(err) => {
+                eprintln!("ERROR: could not open block store {}: {}", path.display(), err);
+                return blockchain;
+            }
+        };
 
-                    if self.blocks[round].tx == block.tx {
-                        self.blocks[round] = block.to_owned();
-                    } else {
-                        self.blocks.retain(|b| b.tx != block.tx);
-                        self.blocks.insert(round, block.to_owned());
-                    }
+        for height in 0..store.get_heights() {
+            match store.get_at(height) {
+                Ok(blocks) => blocks.iter().for_each(|block| { blockchain.add_block(block); }),
+                Err(err) => eprintln!("ERROR: could not read blocks of round {}: {}", height, err),
+            }
+        }
 
-                    self.rehash(round);
-                } else {
-                    for (i, b) in self.blocks[round..].iter().enumerate() {
-                        if b.tx == block.tx {
-                            // discard block
-                            return;
-                        }
-
-                        if b.get_minig_hash() > block.get_minig_hash() {
-                            let first_change_idx = self.blocks.iter()
-                                .position(|b| b.tx != block.tx)
-                                .unwrap_or(i);
-                            self.blocks.retain(|b| b.tx != block.tx);
-                            self.blocks.insert(i, block.to_owned());
-                            self.rehash(first_change_idx);
-                            return;
-                        }
-                    }
+        if let Err(err) = blockchain.validate() {
+            eprintln!("ERROR: loaded blockchain is invalid: {}", err);
+        }
+
+        blockchain.store = Some(store);
+        return blockchain;
+    }
+
+    /// returns the txs which are not part of the chain anymore (after a reorg, without the ones whose seq got used)
+    pub fn add_block(&mut self, block: &Block) -> Vec<Transaction> {
+        // known blocks (like the genesis block, which is not checked) are ignored right away
+        if self.tree.contains(&block.hash) || self.tree.is_orphan(&block.hash) {
+            return Vec::new();
+        }
 
-                    let mut b = block.to_owned();
-                    b.rehash(self.get_cur_hash());
-                    self.blocks.push(b);
+        if let Err(err) = self.check_block(block) {
+            println!("discard block (round: {}): {}", block.round, err);
+            return Vec::new();
+        }
+
+        let mut dropped_txs = Vec::<Transaction>::new();
+        let mut pending = vec![block.to_owned()];
+
+        while let Some(block) = pending.pop() {
+            let hash = block.hash;
+            let round = block.round;
+
+            match self.insert(block) {
+                Ok(true) => pending.extend(self.tree.take_children(&hash)),
+                Ok(false) => continue,
+                Err(err) => {
+                    println!("discard block (round: {}): {}", round, err);
+                    continue;
                 }
-            } else {
-                println!("hashes are not matching");
-                println!("hashes: {} -> {}", self.get_prev_hash(round), block.prev_hash);
-                println!("round: {}", round);
             }
-        }   
+
+            if self.tree.is_better(&hash, self.consensus.as_ref()) {
+                match self.reorg(hash) {
+                    Ok(txs) => dropped_txs.extend(txs),
+                    Err((bad_hash, err)) => {
+                        println!("discard block {}: {}", bad_hash, err);
+                        self.tree.remove_branch(bad_hash);
+                    }
+                }
+            }
+        }
+
+        return dropped_txs.into_iter().filter(|tx| tx.seq >= self.ledger.get_next_seq(&tx.payer)).collect();
     }
 
-    pub fn get_round(&self) -> usize {
-        return self.blocks.len();
+    /// re-checks every block of the chain after the genesis block
+    pub fn validate(&self) -> Result<(), ChainError> {
+        let mut ledger = self.genesis_ledger.clone();
+        let mut blocks = self.blocks();
+        let mut parent = blocks.next().expect("chain starts with the genesis block");
+
+        for block in blocks {
+            let to_err = |reason| ChainError { round: block.round, hash: block.hash, reason };
+
+            self.check_block(block).map_err(to_err)?;
+            Self::check_parent(block, parent).map_err(to_err)?;
+            self.check_target(block, parent).map_err(to_err)?;
+            self.apply_block(&mut ledger, block).map_err(to_err)?;
+
+            parent = block;
+        }
+
+        return Ok(());
+    }
+
+    pub fn balance_of(&self, pub_key: &str) -> Amount {
+        return self.ledger.balance_of(pub_key);
+    }
+
+    pub fn get_next_seq(&self, payer: &str) -> u64 {
+        return self.ledger.get_next_seq(payer);
+    }
+
+    pub fn get_ledger(&self) -> &Ledger {
+        return &self.ledger;
+    }
+
+    pub fn get_reward(&self, round: usize) -> Amount {
+        return self.reward.get_reward(round);
+    }
+
+    pub fn get_consensus(&self) -> &Arc<dyn Consensus> {
+        return &self.consensus;
+    }
+
+    pub fn get_chain_id(&self) -> &Hash {
+        return &self.chain_id;
+    }
+
+    /// checks everything which does not depend on the other blocks
+    fn check_block(&self, block: &Block) -> Result<(), &'static str> {
+        let header = block.get_header();
+        header.check()?;
+        self.consensus.check_seal(&header)?;
+
+        if !block.coinbase.verify(&self.chain_id) {
+            return Err("coinbase is not signed by the miner");
+        }
+
+        if block.txs.iter().any(|tx| !tx.verify(&self.chain_id)) {
+            return Err("tx is not signed by its payer");
+        }
+
+        if block.txs.len() > MAX_BLOCK_TXS {
+            return Err("too many txs");
+        }
+
+        if block.evidence.len() > MAX_BLOCK_EVIDENCE {
+            return Err("too much evidence");
+        }
+
+        for evidence in &block.evidence {
+            evidence.check()?;
+        }
+
+        if block.has_duplicate_leaves() {
+            return Err("tx or evidence is in the block twice");
+        }
+
+        if !block.verify_merkle_root() {
+            return Err("merkle root does not match txs");
+        }
+
+        return Ok(());
+    }
+
+    /// applies the block to the ledger of its parent (the proposer may depend on its state)
+    fn apply_block(&self, ledger: &mut Ledger, block: &Block) -> Result<(), &'static str> {
+        self.consensus.check_proposer(&block.get_header(), ledger)?;
+        return ledger.apply_block(block, &self.reward);
+    }
+
+    fn check_parent(block: &Block, parent: &Block) -> Result<(), &'static str> {
+        return block.get_header().check_parent(&parent.get_header());
     }
 
-    pub fn get_prev_hash(&self, round: usize) -> u64 {
-        if round < 1 || round > self.blocks.len() {
-            return 0x0;
+    fn check_target(&self, block: &Block, parent: &Block) -> Result<(), &'static str> {
+        if block.target != self.get_target(parent) {
+            return Err("target does not match the consensus");
         }
 
-        return self.blocks[round-1].hash;
+        return Ok(());
     }
 
-    pub fn get_tx_ids(&self) -> Vec<u64> {
-        return self.blocks.iter().map(|b| b.tx.id).collect();
+    /// target of the block after `parent`
+    fn get_target(&self, parent: &Block) -> Hash {
+        return self.consensus.get_target(Some(&parent.get_header()), &|hash| self.tree.get(hash).expect("ancestors are in the tree").get_header());
     }
 
-    pub fn get_cur_hash(&self) -> u64 {
-        if let Some(block) = self.blocks.last() {
-            return block.hash;
+    /// adds the block to the tree (returns false if the parent is unknown yet)
+    fn insert(&mut self, block: Block) -> Result<bool, &'static str> {
+        if let Some(parent) = self.tree.get(&block.prev_hash) {
+            Self::check_parent(&block, parent)?;
+            self.check_target(&block, parent)?;
+            // before the block is stored, even if it does not become part of the main chain
+            let (ledger, _, _) = self.replay(block.prev_hash).map_err(|(_, err)| err)?;
+            self.consensus.check_proposer(&block.get_header(), &ledger)?;
+        } else if block.prev_hash == Hash::ZERO {
+            return Err("only the genesis block has no parent");
+        } else {
+            self.tree.add_orphan(block);
+            return Ok(false);
+        }
+
+        if let Some(store) = &mut self.store {
+            if !store.contains(&block.hash) {
+                if let Err(err) = store.append(&block) {
+                    eprintln!("ERROR: could not store block (round: {}): {}", block.round, err);
+                }
+            }
         }
 
-        return 0x0;
+        let weight = self.consensus.get_weight(&block.get_header());
+        self.tree.insert(block, weight);
+        return Ok(true);
     }
 
-    pub fn get_hashes(&self) -> Vec<(u64, u64)> {
-        return self.blocks.iter().map(|b| (b.prev_hash, b.hash)).collect::<Vec<(u64, u64)>>();
+    /// rolls back to the fork point and applies the branch of `new_tip`
+    /// returns the txs of the dropped blocks or the first invalid block of the branch
+    fn reorg(&mut self, new_tip: Hash) -> Result<Vec<Transaction>, (Hash, &'static str)> {
+        let (ledger, branch, fork_round) = self.replay(new_tip)?;
+
+        let dropped = self.tree.set_chain(branch, fork_round);
+        if !dropped.is_empty() {
+            println!("reorg at round {}: {} blocks dropped", fork_round, dropped.len());
+        }
+
+        self.ledger = ledger;
+
+        return Ok(dropped.iter().flat_map(|hash| self.get_block(hash).txs.clone()).collect());
     }
 
-    fn verify(&self, block: &Block, round: usize) -> bool {
-        if round == 0 {
-            return block.prev_hash == 0x0;
+    /// ledger after the block `tip` of the tree (the main chain stays as it is)
+    /// returns it with the branch of `tip` (oldest block first) and the round after the fork point
+    /// or the first invalid block of the branch
+    fn replay(&self, tip: Hash) -> Result<(Ledger, Vec<Hash>, usize), (Hash, &'static str)> {
+        let (branch, fork_round) = self.tree.get_branch(tip);
+
+        let mut ledger = self.ledger.clone();
+        for hash in self.tree.get_chain()[fork_round..].iter().rev() {
+            ledger.undo_block(self.get_block(hash)).map_err(|err| (*hash, err))?;
         }
 
-        return block.prev_hash == self.blocks[round-1].hash;
+        for hash in &branch {
+            self.apply_block(&mut ledger, self.get_block(hash)).map_err(|err| (*hash, err))?;
+        }
+
+        return Ok((ledger, branch, fork_round));
+    }
+
+    /// block of the tree (main chain or fork)
+    fn get_block(&self, hash: &Hash) -> &Block {
+        return self.tree.get(hash).expect("hash is in the tree");
+    }
+
+    /// evidence if another known block for the same slot was sealed by the same validator
+    pub fn find_double_sign(&self, block: &Block) -> Option<DoubleSign> {
+        let header = block.get_header();
+        return self.tree.items()
+            .filter(|b| b.prev_hash == block.prev_hash && b.round == block.round && b.hash != block.hash)
+            .find_map(|b| self.consensus.find_double_sign(&b.get_header(), &header, &self.ledger));
+    }
+
+    /// true for every block in the tree (main chain and forks, no orphans)
+    pub fn contains(&self, hash: &Hash) -> bool {
+        return self.tree.contains(hash);
+    }
+
+    /// the block waits for its parent
+    pub fn is_orphan(&self, hash: &Hash) -> bool {
+        return self.tree.is_orphan(hash);
+    }
+
+    /// up to `max` blocks of the main chain, starting at `round`
+    pub fn get_blocks(&self, round: usize, max: usize) -> Vec<Block> {
+        return self.blocks().skip(round).take(max).cloned().collect();
+    }
+
+    fn blocks(&self) -> impl DoubleEndedIterator<Item = &Block> {
+        return self.tree.chain();
+    }
+
+    pub fn get_next_target(&self) -> Hash {
+        return self.get_target(self.blocks().last().expect("chain starts with the genesis block"));
+    }
+
+    pub fn get_round(&self) -> usize {
+        return self.tree.get_round();
+    }
+
+    /// median fee rate (units per 1000 bytes) of the txs in the last `blocks` blocks
+    pub fn get_median_fee_rate(&self, blocks: usize) -> Option<u64> {
+        let mut fee_rates = self.blocks().rev().take(blocks)
+            .flat_map(|b| b.txs.iter().map(|tx| tx.get_fee_rate()))
+            .collect::<Vec<u64>>();
+        fee_rates.sort_unstable();
+
+        return fee_rates.get(fee_rates.len() / 2).copied();
+    }
+
+    pub fn get_tx_ids(&self) -> Vec<Hash> {
+        return self.blocks().flat_map(|b| b.txs.iter().map(|tx| tx.gen_hash())).collect();
+    }
+
+    pub fn get_tx(&self, tx_id: Hash) -> Option<&Transaction> {
+        return self.blocks().flat_map(|b| &b.txs).find(|tx| tx.gen_hash() == tx_id);
+    }
+
+    /// hash of the block containing the tx and the inclusion proof of the tx
+    pub fn get_merkle_proof(&self, tx_id: Hash) -> Option<(Hash, MerkleProof)> {
+        return self.blocks().find_map(|b| b.merkle_proof(tx_id).map(|proof| (b.hash, proof)));
+    }
+
+    /// `count` proofs of the txs of the main chain paying or paid by `pub_key` (for light clients),
+    /// skipping the first `from` ones
+    pub fn get_proofs_of(&self, pub_key: &str, from: usize, count: usize) -> Vec<TxProof> {
+        return self.blocks().flat_map(|b| b.get_proofs_of(pub_key)).skip(from).take(count).collect();
     }
 
-    fn rehash(&mut self, round: usize) {
-        let mut prev_hash = self.blocks[round].hash;
-        for b in &mut self.blocks[round+1..] {
-            b.rehash(prev_hash);
-            prev_hash = b.hash;
+    pub fn verify_merkle_proof(&self, block_hash: &Hash, tx: &Transaction, proof: &MerkleProof) -> bool {
+        if !self.tree.is_in_chain(block_hash) {
+            return false;
         }
+
+        return self.get_block(block_hash).verify_merkle_proof(tx, proof);
+    }
+
+    pub fn get_cur_hash(&self) -> Hash {
+        return self.tree.get_cur_hash();
+    }
+
+    pub fn get_hashes(&self) -> Vec<(Hash, Hash)> {
+        return self.blocks().map(|b| (b.prev_hash, b.hash)).collect::<Vec<(Hash, Hash)>>();
     }
 }
 
 impl Display for Blockchain {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         return write!(f, "------------ Greychain ------------\n{}",
-                      self.blocks
-                      .iter()
+                      self.blocks()
                       .map(|block| block.to_string())
                       .collect::<String>());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rsa::{pss::BlindedSigningKey, sha2::Sha256};
+
+    use crate::{blockchain::{Transaction, Block, ChainSpec, Allocation, Amount}, crypto::Hash, test_utils::{create_signer, solve}};
+
+    use super::Blockchain;
+
+    fn mine(chain_id: &Hash, txs: Vec<Transaction>, miner: &String, sign_key: &BlindedSigningKey<Sha256>, prev_hash: Hash, round: usize) -> Block {
+        return mine_with_target(chain_id, txs, miner, sign_key, prev_hash, round, Hash::max_with_leading_zeros(20));
+    }
+
+    fn mine_with_target(chain_id: &Hash, txs: Vec<Transaction>, miner: &String, sign_key: &BlindedSigningKey<Sha256>, prev_hash: Hash, round: usize, target: Hash) -> Block {
+        let coinbase = Transaction::new_coinbase(chain_id, miner, round, Amount::from_gry(50), sign_key);
+        let timestamp = Block::gen_timestamp();
+        let header_hash = Block::gen_header_hash(prev_hash, round, timestamp, target, Block::gen_merkle_root(&txs, &[], &coinbase));
+        let seal = solve(&header_hash, &target);
+
+        return Block::new(txs, Vec::new(), coinbase, prev_hash, round, timestamp, target, seal);
+    }
+
+    #[test]
+    fn reorg_to_most_work() {
+        let (miner, sign_key) = create_signer();
+        let payee = "payee".to_string();
+        let spec = ChainSpec { allocations: vec![Allocation { pub_key: miner.clone(), amount: Amount::from_gry(5) }], ..ChainSpec::default() };
+        let mut blockchain = Blockchain::new(&spec);
+        let chain_id = *blockchain.get_chain_id();
+        let genesis = blockchain.get_cur_hash();
+        assert_eq!(blockchain.balance_of(&miner), Amount::from_gry(5));
+
+        // blocks have to build on the genesis block
+        blockchain.add_block(&mine(&chain_id, Vec::new(), &miner, &sign_key, Hash::ZERO, 1));
+        assert_eq!(blockchain.get_cur_hash(), genesis);
+
+        let first = mine(&chain_id, Vec::new(), &miner, &sign_key, genesis, 1);
+        assert!(blockchain.add_block(&first).is_empty());
+
+        let tx = Transaction::new(&chain_id, &miner, &payee, 0, Amount::from_gry(10), Amount::ZERO, &sign_key);
+        let a1 = mine(&chain_id, vec![tx.clone()], &miner, &sign_key, first.hash, 2);
+        blockchain.add_block(&a1);
+        assert_eq!(blockchain.get_cur_hash(), a1.hash);
+        assert_eq!(blockchain.balance_of(&payee), Amount::from_gry(10));
+
+        // a longer branch without the tx (b2 arrives before its parent)
+        let b1 = mine(&chain_id, Vec::new(), &miner, &sign_key, first.hash, 2);
+        let b2 = mine(&chain_id, Vec::new(), &miner, &sign_key, b1.hash, 3);
+        assert!(blockchain.add_block(&b2).is_empty());
+        assert_eq!(blockchain.get_cur_hash(), a1.hash);
+        assert!(blockchain.is_orphan(&b2.hash) && !blockchain.contains(&b2.hash));
+
+        let dropped = blockchain.add_block(&b1);
+        assert!(!blockchain.is_orphan(&b2.hash));
+        assert!(dropped.len() == 1 && dropped[0] == tx);
+        assert_eq!(blockchain.get_cur_hash(), b2.hash);
+        assert_eq!(blockchain.get_round(), 4);
+        assert_eq!(blockchain.balance_of(&payee), Amount::ZERO);
+        assert_eq!(blockchain.balance_of(&miner), Amount::from_gry(155));
+
+        // blocks can not be moved to another parent without redoing the work
+        let mut moved = a1.clone();
+        moved.prev_hash = b2.hash;
+        moved.round = 4;
+        blockchain.add_block(&moved);
+        assert_eq!(blockchain.get_cur_hash(), b2.hash);
+        assert_eq!(blockchain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn duplicated_last_tx_is_rejected() {
+        let (miner, sign_key) = create_signer();
+        let payee = "payee".to_string();
+        let spec = ChainSpec { allocations: vec![Allocation { pub_key: miner.clone(), amount: Amount::from_gry(5) }], ..ChainSpec::default() };
+        let mut blockchain = Blockchain::new(&spec);
+        let chain_id = *blockchain.get_chain_id();
+        let genesis = blockchain.get_cur_hash();
+
+        let a1 = mine(&chain_id, Vec::new(), &miner, &sign_key, genesis, 1);
+        let a2 = mine(&chain_id, Vec::new(), &miner, &sign_key, a1.hash, 2);
+        blockchain.add_block(&a1);
+        blockchain.add_block(&a2);
+
+        let txs = vec![
+            Transaction::new(&chain_id, &miner, &payee, 0, Amount::from_gry(1), Amount::ZERO, &sign_key),
+            Transaction::new(&chain_id, &miner, &payee, 1, Amount::from_gry(1), Amount::ZERO, &sign_key),
+        ];
+        let b1 = mine(&chain_id, txs, &miner, &sign_key, genesis, 1);
+        let b2 = mine(&chain_id, Vec::new(), &miner, &sign_key, b1.hash, 2);
+        let b3 = mine(&chain_id, Vec::new(), &miner, &sign_key, b2.hash, 3);
+
+        // coinbase, t1, t2 and coinbase, t1, t2, t2 have the same root (and so the same block hash)
+        let mut mutated = b1.clone();
+        mutated.txs.push(b1.txs[1].clone());
+        assert!(mutated.verify_merkle_root());
+        assert_eq!(mutated.get_header().check(), Ok(()));
+
+        // as a side branch it would not be applied (and would take the place of the real block)
+        blockchain.add_block(&mutated);
+        assert!(!blockchain.contains(&b1.hash));
+
+        for block in [&b1, &b2, &b3] {
+            blockchain.add_block(block);
+        }
+        assert_eq!(blockchain.get_cur_hash(), b3.hash);
+        assert_eq!(blockchain.balance_of(&payee), Amount::from_gry(2));
+    }
+
+    #[test]
+    fn validate_names_first_bad_block() {
+        let (miner, sign_key) = create_signer();
+        let mut blockchain = Blockchain::new(&ChainSpec::default());
+        let chain_id = *blockchain.get_chain_id();
+
+        for round in 1..4 {
+            let block = mine(&chain_id, Vec::new(), &miner, &sign_key, blockchain.get_cur_hash(), round);
+            blockchain.add_block(&block);
+        }
+        assert_eq!(blockchain.validate(), Ok(()));
+
+        let hash = blockchain.tree.get_chain()[1];
+        blockchain.tree.get_mut(&hash).unwrap().timestamp += 1;
+
+        let err = blockchain.validate().unwrap_err();
+        assert_eq!((err.round, err.hash, err.reason), (1, hash, "hash does not match the header"));
+    }
+
+    #[test]
+    fn reload_from_store() {
+        let (miner, sign_key) = create_signer();
+
+        let path = std::env::temp_dir().join(format!("greychain-chain-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut blockchain = Blockchain::load(&path, &ChainSpec::default());
+
+        let chain_id = *blockchain.get_chain_id();
+        for round in 1..3 {
+            let block = mine(&chain_id, Vec::new(), &miner, &sign_key, blockchain.get_cur_hash(), round);
+            blockchain.add_block(&block);
+        }
+
+        let reloaded = Blockchain::load(&path, &ChainSpec::default());
+        assert_eq!(reloaded.get_round(), 3);
+        assert_eq!(reloaded.get_cur_hash(), blockchain.get_cur_hash());
+        assert_eq!(reloaded.balance_of(&miner), Amount::from_gry(100));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn blocks_have_to_meet_the_difficulty() {
+        let (miner, sign_key) = create_signer();
+        let mut spec = ChainSpec { genesis_time: Block::gen_timestamp() as u64, ..ChainSpec::default() };
+        spec.difficulty.pow_limit_bits = 8;
+        spec.difficulty.retarget_interval = 3;
+        spec.difficulty.block_time = 60_000_000;
+        let mut blockchain = Blockchain::new(&spec);
+        let chain_id = *blockchain.get_chain_id();
+
+        let easy = mine_with_target(&chain_id, Vec::new(), &miner, &sign_key, blockchain.get_cur_hash(), 1, Hash::max_with_leading_zeros(4));
+        blockchain.add_block(&easy);
+        assert_eq!(blockchain.get_round(), 1);
+
+        for round in 1..3 {
+            let block = mine_with_target(&chain_id, Vec::new(), &miner, &sign_key, blockchain.get_cur_hash(), round, blockchain.get_next_target());
+            blockchain.add_block(&block);
+        }
+
+        // blocks came way faster than every minute (since the genesis block)
+        assert_eq!(blockchain.get_round(), 3);
+        assert_eq!(blockchain.get_next_target().work(), Hash::max_with_leading_zeros(8).work() * 4);
+        assert_eq!(blockchain.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,51 @@
+use rsa::{pss::BlindedSigningKey, sha2::Sha256, signature::RandomizedSigner};
+
+use crate::crypto::Hash;
+
+use super::{BlockHeader, Ledger, DoubleSign};
+
+/// rules for who may seal blocks, how seals are checked and which fork wins
+pub trait Consensus: Send + Sync {
+    /// target of the block after `parent` (`get_header` has to return the header of every ancestor of `parent`)
+    fn get_target(&self, parent: Option<&BlockHeader>, get_header: &dyn Fn(&Hash) -> BlockHeader) -> Hash;
+
+    /// checks the seal of a header (its hash is already checked against its content)
+    fn check_seal(&self, header: &BlockHeader) -> Result<(), &'static str>;
+
+    /// checks the sealer against the chain state of the parent (`ledger`), e.g. the stakes
+    fn check_proposer(&self, _header: &BlockHeader, _ledger: &Ledger) -> Result<(), &'static str> {
+        return Ok(());
+    }
+
+    /// false if `check_seal` does not check everything about the sealer (so light clients can not follow the chain)
+    fn checks_headers(&self) -> bool {
+        return true;
+    }
+
+    /// weight of the block in the fork choice (the branch with the most weight wins)
+    fn get_weight(&self, header: &BlockHeader) -> u128;
+
+    /// compares the branch of `hash` with the one of the current tip
+    /// (ties are broken by the lower hash, so every node picks the same tip)
+    fn is_better(&self, weight: u128, hash: &Hash, tip_weight: u128, tip_hash: &Hash) -> bool {
+        return weight > tip_weight || (weight == tip_weight && hash < tip_hash);
+    }
+
+    /// true if `pub_key` may seal the block of `round` on top of `prev_hash` (with `ledger` as the chain state of the parent)
+    fn can_seal(&self, pub_key: &str, prev_hash: &Hash, round: usize, ledger: &Ledger) -> bool;
+
+    /// produces the seal of the header with `header_hash`
+    /// `progress` gets the number of attempts since its last call and cancels the search by returning false
+    fn seal(&self, header_hash: &Hash, target: &Hash, sign_key: &BlindedSigningKey<Sha256>, progress: &mut dyn FnMut(u64) -> bool) -> Option<Vec<u8>>;
+
+    /// evidence that the two headers were sealed by the same validator (only for engines which can slash)
+    fn find_double_sign(&self, _first: &BlockHeader, _second: &BlockHeader, _ledger: &Ledger) -> Option<DoubleSign> {
+        return None;
+    }
+}
+
+/// seal of the engines sealing by signature (see `BlockHeader::is_signed_by`)
+pub(super) fn sign_header(header_hash: &Hash, sign_key: &BlindedSigningKey<Sha256>) -> Vec<u8> {
+    let sign = sign_key.sign_with_rng(&mut rand::thread_rng(), &header_hash.0);
+    return Box::<[u8]>::from(sign).to_vec();
+}
@@ -0,0 +1,114 @@
+use crate::crypto::{Hash, HASH_BYTES};
+
+use super::{BlockHeader, spec::DifficultySpec};
+
+/// max factor the target can change by per retarget
+const MAX_ADJUSTMENT: u128 = 4;
+
+/// proof of work target (hashes have to be below it) and how it follows the block time
+#[derive(Clone, Copy)]
+pub struct Difficulty {
+    /// target of the first blocks and the easiest target allowed
+    pub pow_limit: Hash,
+    /// target is adjusted every `retarget_interval` rounds
+    pub retarget_interval: usize,
+    /// wanted time between two blocks (in micro secs)
+    pub block_time: u128,
+}
+
+impl Difficulty {
+    /// target of the block after `parent` (retargeted every `retarget_interval` rounds,
+    /// never if the interval is too short to measure a timespan)
+    /// `get_header` has to return the header of every ancestor of `parent`
+    pub fn get_target(&self, parent: Option<&BlockHeader>, get_header: impl Fn(&Hash) -> BlockHeader) -> Hash {
+        let Some(parent) = parent else {
+            return self.pow_limit;
+        };
+
+        if self.retarget_interval < 2 || (parent.round + 1) % self.retarget_interval != 0 {
+            return parent.target;
+        }
+
+        let mut first = parent.clone();
+        for _ in 1..self.retarget_interval {
+            first = get_header(&first.prev_hash);
+        }
+
+        return self.retarget(parent.target, parent.timestamp.saturating_sub(first.timestamp));
+    }
+
+    /// new target from the time the last `retarget_interval` blocks took
+    pub fn retarget(&self, target: Hash, timespan: u128) -> Hash {
+        let expected = self.block_time * (self.retarget_interval as u128 - 1);
+        let timespan = timespan.clamp(expected / MAX_ADJUSTMENT, expected * MAX_ADJUSTMENT);
+
+        let target = mul_div(&target, timespan.max(1), expected.max(1));
+        return target.min(self.pow_limit);
+    }
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        return Difficulty::from(&DifficultySpec::default());
+    }
+}
+
+/// hash * mul / div as 256-bit big-endian numbers (saturates at the biggest hash)
+fn mul_div(hash: &Hash, mul: u128, div: u128) -> Hash {
+    // u32 limbs so that limb * u64 fits into u128 (least significant limb first)
+    const LIMBS: usize = HASH_BYTES / 4;
+    let mut limbs = [0u128; LIMBS + 4];
+    for (i, chunk) in hash.0.rchunks(4).enumerate() {
+        limbs[i] = u32::from_be_bytes(chunk.try_into().unwrap()) as u128;
+    }
+
+    let (mul, div) = (mul.min(u64::MAX as u128), div.min(u64::MAX as u128));
+
+    let mut carry = 0u128;
+    for limb in limbs.iter_mut() {
+        let value = *limb * mul + carry;
+        *limb = value & 0xffff_ffff;
+        carry = value >> 32;
+    }
+
+    let mut rem = 0u128;
+    for limb in limbs.iter_mut().rev() {
+        let value = (rem << 32) | *limb;
+        *limb = value / div;
+        rem = value % div;
+    }
+
+    if limbs[LIMBS..].iter().any(|limb| *limb != 0) {
+        return Hash([0xff; HASH_BYTES]);
+    }
+
+    let mut bytes = [0u8; HASH_BYTES];
+    for (i, chunk) in bytes.rchunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&(limbs[i] as u32).to_be_bytes());
+    }
+
+    return Hash(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::Hash;
+
+    use super::Difficulty;
+
+    #[test]
+    fn retarget() {
+        let difficulty = Difficulty { pow_limit: Hash::max_with_leading_zeros(8), retarget_interval: 11, block_time: 100 };
+        let expected = 100 * 10;
+        let target = Hash::max_with_leading_zeros(20);
+
+        assert_eq!(difficulty.retarget(target, expected), target);
+        assert_eq!(difficulty.retarget(target, expected / 2).work(), target.work() * 2);
+        assert_eq!(difficulty.retarget(target, expected * 2).work(), target.work() / 2);
+
+        // changes are limited per retarget and the target can not get easier than the limit
+        assert_eq!(difficulty.retarget(target, 0).work(), target.work() * 4);
+        assert_eq!(difficulty.retarget(target, expected * 100).work(), target.work() / 4);
+        assert_eq!(difficulty.retarget(difficulty.pow_limit, expected * 4), difficulty.pow_limit);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{net::serialize::Serializer, crypto::Hash};
+
+use super::BlockHeader;
+
+/// two different blocks for the same slot (same parent and round) signed by the same validator
+#[derive(Clone, Debug, PartialEq, Serializer)]
+pub struct DoubleSign {
+    pub validator: String,
+    pub first: BlockHeader,
+    pub second: BlockHeader,
+}
+
+impl DoubleSign {
+    pub fn new(validator: String, first: BlockHeader, second: BlockHeader) -> DoubleSign {
+        return DoubleSign { validator, first, second };
+    }
+
+    /// checks that the headers are signed by the validator and really compete for the same slot
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.first.prev_hash != self.second.prev_hash || self.first.round != self.second.round {
+            return Err("headers are not for the same slot");
+        }
+
+        if self.first.hash == self.second.hash {
+            return Err("headers are the same");
+        }
+
+        if !self.first.verify_hash() || !self.second.verify_hash() {
+            return Err("hash does not match the header");
+        }
+
+        if !self.first.is_signed_by(&self.validator) || !self.second.is_signed_by(&self.validator) {
+            return Err("headers are not signed by the validator");
+        }
+
+        return Ok(());
+    }
+
+    /// leaf of the merkle tree of the block containing the evidence
+    pub fn gen_hash(&self) -> Hash {
+        let mut bytes = self.validator.as_bytes().to_vec();
+        bytes.extend_from_slice(&self.first.hash.0);
+        bytes.extend_from_slice(&self.second.hash.0);
+
+        return Hash::digest(&bytes);
+    }
+}
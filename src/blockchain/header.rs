@@ -0,0 +1,71 @@
+use rsa::pss::Signature;
+
+use crate::{net::serialize::Serializer, crypto::{Hash, verify_sign}};
+
+use super::Block;
+
+/// max time a block timestamp may be ahead of the local clock (in micro secs)
+const MAX_FUTURE_TIME: u128 = 2 * 60 * 60 * 1_000_000;
+
+/// everything of a block the seal commits to (the txs only through the merkle root)
+#[derive(Clone, Debug, PartialEq, Serializer)]
+pub struct BlockHeader {
+    pub prev_hash: Hash,
+    pub round: usize,
+    pub timestamp: u128,
+    /// target of the consensus engine
+    pub target: Hash,
+    pub merkle_root: Hash,
+    pub seal: Vec<u8>,
+    pub hash: Hash,
+}
+
+impl BlockHeader {
+    /// checks if the stored hash matches the header
+    pub fn verify_hash(&self) -> bool {
+        return self.hash == Block::gen_hash(&self.gen_header_hash(), &self.seal);
+    }
+
+    /// hash of everything the seal commits to
+    pub fn gen_header_hash(&self) -> Hash {
+        return Block::gen_header_hash(self.prev_hash, self.round, self.timestamp, self.target, self.merkle_root);
+    }
+
+    /// checks if the seal is the signature of `pub_key` (for the engines sealing by signature)
+    pub fn is_signed_by(&self, pub_key: &str) -> bool {
+        return match Signature::try_from(self.seal.as_slice()) {
+            Ok(sign) => verify_sign(pub_key, &self.gen_header_hash().0, &sign),
+            Err(_) => false
+        };
+    }
+
+    /// checks everything which does not depend on other headers or the consensus engine
+    pub fn check(&self) -> Result<(), &'static str> {
+        if !self.verify_hash() {
+            return Err("hash does not match the header");
+        }
+
+        if self.timestamp > Block::gen_timestamp() + MAX_FUTURE_TIME {
+            return Err("timestamp is too far in the future");
+        }
+
+        return Ok(());
+    }
+
+    /// checks the link to the parent (every block but the genesis block has one)
+    pub fn check_parent(&self, parent: &BlockHeader) -> Result<(), &'static str> {
+        if self.prev_hash != parent.hash {
+            return Err("prev hash does not match its parent");
+        }
+
+        if self.round != parent.round + 1 {
+            return Err("round does not follow its parent");
+        }
+
+        if self.timestamp < parent.timestamp {
+            return Err("timestamp is before its parent");
+        }
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use crate::crypto::Hash;
+
+use super::{tree::ChainTree, BlockHeader, Consensus, ChainSpec, Transaction, MerkleProof};
+
+/// chain of headers without the txs (for light clients)
+pub struct HeaderChain {
+    /// all known headers and the main chain (the same fork choice as `Blockchain`)
+    tree: ChainTree<BlockHeader>,
+    consensus: Arc<dyn Consensus>,
+}
+
+impl HeaderChain {
+    /// chain with only the genesis header of the spec
+    pub fn new(spec: &ChainSpec) -> HeaderChain {
+        let consensus = spec.create_consensus();
+        let genesis = spec.gen_genesis(consensus.as_ref()).get_header();
+        return HeaderChain { tree: ChainTree::new(genesis), consensus };
+    }
+
+    /// headers with an unknown parent wait for it as orphans (returns false for them)
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<bool, &'static str> {
+        if self.tree.contains(&header.hash) {
+            return Ok(true);
+        }
+
+        if self.tree.is_orphan(&header.hash) {
+            return Ok(false);
+        }
+
+        // without the blocks there is no chain state to check the proposer against
+        if !self.consensus.checks_headers() {
+            return Err("consensus can not be checked by headers alone");
+        }
+
+        header.check()?;
+        self.consensus.check_seal(&header)?;
+
+        if !self.tree.contains(&header.prev_hash) {
+            if header.prev_hash == Hash::ZERO {
+                return Err("only the genesis header has no parent");
+            }
+
+            self.tree.add_orphan(header);
+            return Ok(false);
+        }
+
+        let mut pending = vec![header];
+        while let Some(header) = pending.pop() {
+            let hash = header.hash;
+            let round = header.round;
+
+            if let Err(err) = self.insert(header) {
+                println!("discard header (round: {}): {}", round, err);
+                continue;
+            }
+            pending.extend(self.tree.take_children(&hash));
+
+            if self.tree.is_better(&hash, self.consensus.as_ref()) {
+                let (branch, fork_round) = self.tree.get_branch(hash);
+                self.tree.set_chain(branch, fork_round);
+            }
+        }
+
+        return Ok(true);
+    }
+
+    /// checks the header against its parent (which is in the tree) and adds it
+    fn insert(&mut self, header: BlockHeader) -> Result<(), &'static str> {
+        let parent = self.tree.get(&header.prev_hash).ok_or("parent is unknown")?;
+        header.check_parent(parent)?;
+        if header.target != self.consensus.get_target(Some(parent), &|hash| self.tree.get(hash).expect("ancestors are in the tree").clone()) {
+            return Err("target does not match the consensus");
+        }
+
+        let weight = self.consensus.get_weight(&header);
+        self.tree.insert(header, weight);
+        return Ok(());
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        return self.tree.contains(hash);
+    }
+
+    /// the header waits for its parent
+    pub fn is_orphan(&self, hash: &Hash) -> bool {
+        return self.tree.is_orphan(hash);
+    }
+
+    pub fn is_in_chain(&self, hash: &Hash) -> bool {
+        return self.tree.is_in_chain(hash);
+    }
+
+    /// checks that the tx is part of a block of the main chain
+    pub fn verify_merkle_proof(&self, block_hash: &Hash, tx: &Transaction, proof: &MerkleProof) -> bool {
+        return match self.tree.get(block_hash) {
+            Some(header) if self.tree.is_in_chain(block_hash) => proof.verify(&tx.gen_hash(), &header.merkle_root),
+            _ => false
+        };
+    }
+
+    /// (prev hash, hash) of the main chain (like `Blockchain::get_hashes`)
+    pub fn get_hashes(&self) -> Vec<(Hash, Hash)> {
+        return self.tree.chain().map(|header| (header.prev_hash, header.hash)).collect();
+    }
+
+    pub fn get_round(&self) -> usize {
+        return self.tree.get_round();
+    }
+
+    pub fn get_cur_hash(&self) -> Hash {
+        return self.tree.get_cur_hash();
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use super::{Block, Transaction, RewardSchedule, Amount};
+
+/// account balances built by applying the blocks of a chain
+#[derive(Clone, Default)]
+pub struct Ledger {
+    balances: HashMap<String, Amount>,
+    /// seqs of the txs of each payer (increasing)
+    seqs: HashMap<String, Vec<u64>>,
+    /// balance locked by stake txs
+    stakes: HashMap<String, Amount>,
+    /// validators caught double signing with the stake they lost
+    slashed: HashMap<String, Amount>,
+}
+
+impl Ledger {
+    /// ledger before the first block (a key may be listed more than once)
+    pub fn with_balances(balances: &[(String, Amount)]) -> Result<Ledger, &'static str> {
+        let mut ledger = Ledger::default();
+        for (pub_key, amount) in balances {
+            ledger.credit(pub_key, *amount)?;
+        }
+
+        return Ok(ledger);
+    }
+
+    pub fn apply_block(&mut self, block: &Block, reward: &RewardSchedule) -> Result<(), &'static str> {
+        if !block.coinbase.is_coinbase() {
+            return Err("block has no coinbase");
+        }
+
+        let fees = Block::gen_fees(&block.txs).ok_or("fees overflow")?;
+        if Some(block.coinbase.amount) != reward.get_reward(block.round).checked_add(fees) {
+            return Err("invalid block reward");
+        }
+
+        for tx in &block.txs {
+            self.apply(tx)?;
+        }
+
+        for evidence in &block.evidence {
+            self.slash(&evidence.validator)?;
+        }
+
+        return self.credit(&block.coinbase.payee, block.coinbase.amount);
+    }
+
+    /// reverts `apply_block` (only valid for the last applied block)
+    pub fn undo_block(&mut self, block: &Block) -> Result<(), &'static str> {
+        self.debit(&block.coinbase.payee, block.coinbase.amount)?;
+
+        for evidence in block.evidence.iter().rev() {
+            let stake = self.slashed.remove(&evidence.validator).ok_or("validator is not slashed")?;
+            if !stake.is_zero() {
+                self.stakes.insert(evidence.validator.clone(), stake);
+            }
+        }
+
+        for tx in block.txs.iter().rev() {
+            let seqs = self.seqs.get_mut(&tx.payer).ok_or("tx is not in the chain")?;
+            if seqs.last() != Some(&tx.seq) {
+                return Err("tx is not in the chain");
+            }
+            seqs.pop();
+            if seqs.is_empty() {
+                self.seqs.remove(&tx.payer);
+            }
+
+            if tx.is_stake() {
+                self.unstake(&tx.payer, tx.amount)?;
+            } else {
+                self.debit(&tx.payee, tx.amount)?;
+            }
+            self.credit(&tx.payer, tx.get_cost().ok_or("fee overflows")?)?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), &'static str> {
+        if tx.is_coinbase() {
+            return Err("unexpected coinbase");
+        }
+
+        if tx.amount.is_zero() {
+            return Err("invalid amount");
+        }
+
+        let cost = tx.get_cost().ok_or("fee overflows")?;
+        let payer_balance = self.balance_of(&tx.payer).checked_sub(cost)
+            .ok_or("payer balance would be negative")?;
+        if tx.is_stake() {
+            if self.is_slashed(&tx.payer) {
+                return Err("validator is slashed");
+            }
+            self.stake_of(&tx.payer).checked_add(tx.amount).ok_or("stake would overflow")?;
+        } else if tx.payee != tx.payer {
+            self.balance_of(&tx.payee).checked_add(tx.amount).ok_or("payee balance would overflow")?;
+        }
+
+        if self.get_last_seq(&tx.payer).is_some_and(|last| tx.seq <= last) {
+            return Err("seq is not higher than the last one of the payer");
+        }
+
+        self.seqs.entry(tx.payer.clone()).or_default().push(tx.seq);
+        self.balances.insert(tx.payer.clone(), payer_balance);
+        if tx.is_stake() {
+            return self.lock(&tx.payer, tx.amount);
+        }
+        return self.credit(&tx.payee, tx.amount);
+    }
+
+    /// burns the stake of a validator caught double signing (a validator is slashed only once)
+    fn slash(&mut self, validator: &str) -> Result<(), &'static str> {
+        if self.is_slashed(validator) {
+            return Err("validator is already slashed");
+        }
+
+        let stake = self.stakes.remove(validator).unwrap_or(Amount::ZERO);
+        self.slashed.insert(validator.to_string(), stake);
+        return Ok(());
+    }
+
+    fn lock(&mut self, validator: &str, amount: Amount) -> Result<(), &'static str> {
+        let stake = self.stake_of(validator).checked_add(amount).ok_or("stake would overflow")?;
+        self.stakes.insert(validator.to_string(), stake);
+        return Ok(());
+    }
+
+    fn unstake(&mut self, validator: &str, amount: Amount) -> Result<(), &'static str> {
+        let stake = self.stake_of(validator).checked_sub(amount).ok_or("stake would be negative")?;
+        if stake.is_zero() {
+            self.stakes.remove(validator);
+        } else {
+            self.stakes.insert(validator.to_string(), stake);
+        }
+        return Ok(());
+    }
+
+    pub fn stake_of(&self, validator: &str) -> Amount {
+        return *self.stakes.get(validator).unwrap_or(&Amount::ZERO);
+    }
+
+    pub fn get_stakes(&self) -> &HashMap<String, Amount> {
+        return &self.stakes;
+    }
+
+    pub fn is_slashed(&self, validator: &str) -> bool {
+        return self.slashed.contains_key(validator);
+    }
+
+    pub fn balance_of(&self, pub_key: &str) -> Amount {
+        return *self.balances.get(pub_key).unwrap_or(&Amount::ZERO);
+    }
+
+    fn credit(&mut self, pub_key: &str, amount: Amount) -> Result<(), &'static str> {
+        let balance = self.balance_of(pub_key).checked_add(amount).ok_or("payee balance would overflow")?;
+        self.balances.insert(pub_key.to_string(), balance);
+        return Ok(());
+    }
+
+    fn debit(&mut self, pub_key: &str, amount: Amount) -> Result<(), &'static str> {
+        let balance = self.balance_of(pub_key).checked_sub(amount).ok_or("balance would be negative")?;
+        self.balances.insert(pub_key.to_string(), balance);
+        return Ok(());
+    }
+
+    /// lowest seq the next tx of the payer may have
+    pub fn get_next_seq(&self, payer: &str) -> u64 {
+        return self.get_last_seq(payer).map_or(0, |seq| seq.saturating_add(1));
+    }
+
+    fn get_last_seq(&self, payer: &str) -> Option<u64> {
+        return self.seqs.get(payer).and_then(|seqs| seqs.last()).copied();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{blockchain::{Transaction, Block, RewardSchedule, Amount}, crypto::Hash, test_utils::create_signer};
+
+    use super::Ledger;
+
+    #[test]
+    fn overdraft() {
+        let (payer, sign_key) = create_signer();
+        let payee = "payee".to_string();
+        let reward = RewardSchedule::default();
+        let mut ledger = Ledger::default();
+
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &payer, 0, reward.get_reward(0), &sign_key);
+        ledger.apply_block(&Block::new(Vec::new(), Vec::new(), coinbase, Hash::ZERO, 0, 0, Hash::ZERO, Vec::new()), &reward).unwrap();
+        assert_eq!(ledger.balance_of(&payer), Amount::from_gry(50));
+
+        ledger.apply(&Transaction::new(&Hash::ZERO, &payer, &payee, 0, Amount::from_gry(30), Amount::ZERO, &sign_key)).unwrap();
+        assert_eq!(ledger.balance_of(&payer), Amount::from_gry(20));
+        assert_eq!(ledger.balance_of(&payee), Amount::from_gry(30));
+
+        assert!(ledger.apply(&Transaction::new(&Hash::ZERO, &payer, &payee, 1, Amount::from_gry(30), Amount::ZERO, &sign_key)).is_err());
+        assert!(ledger.apply(&Transaction::new(&Hash::ZERO, &payer, &payee, 1, Amount::ZERO, Amount::ZERO, &sign_key)).is_err());
+
+        let tx = Transaction::new(&Hash::ZERO, &payer, &payee, 2, Amount::from_gry(5), Amount::ZERO, &sign_key);
+        ledger.apply(&tx).unwrap();
+        assert!(ledger.apply(&tx).is_err());
+        assert_eq!(ledger.balance_of(&payer), Amount::from_gry(15));
+
+        // seqs have to increase (gaps are allowed)
+        assert!(ledger.apply(&Transaction::new(&Hash::ZERO, &payer, &payee, 1, Amount::from_gry(5), Amount::ZERO, &sign_key)).is_err());
+        assert_eq!(ledger.get_next_seq(&payer), 3);
+    }
+
+    #[test]
+    fn undo_block() {
+        let (payer, sign_key) = create_signer();
+        let payee = "payee".to_string();
+        let reward = RewardSchedule::default();
+        let mut ledger = Ledger::default();
+
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &payer, 0, reward.get_reward(0), &sign_key);
+        ledger.apply_block(&Block::new(Vec::new(), Vec::new(), coinbase, Hash::ZERO, 0, 0, Hash::ZERO, Vec::new()), &reward).unwrap();
+
+        // the fee goes to the miner of the block
+        let tx = Transaction::new(&Hash::ZERO, &payer, &payee, 0, Amount::from_gry(30), Amount::from_gry(1), &sign_key);
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &payee, 1, reward.get_reward(1), &sign_key);
+        let unpaid = Block::new(vec![tx.clone()], Vec::new(), coinbase, Hash::ZERO, 1, 0, Hash::ZERO, Vec::new());
+        assert!(ledger.clone().apply_block(&unpaid, &reward).is_err());
+
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &payee, 1, Amount::from_gry(51), &sign_key);
+        let block = Block::new(vec![tx.clone()], Vec::new(), coinbase, Hash::ZERO, 1, 0, Hash::ZERO, Vec::new());
+        ledger.apply_block(&block, &reward).unwrap();
+        assert_eq!(ledger.balance_of(&payer), Amount::from_gry(19));
+        assert_eq!(ledger.balance_of(&payee), Amount::from_gry(81));
+
+        ledger.undo_block(&block).unwrap();
+        assert_eq!(ledger.balance_of(&payer), Amount::from_gry(50));
+        assert_eq!(ledger.balance_of(&payee), Amount::ZERO);
+        assert_eq!(ledger.get_next_seq(&payer), 0);
+    }
+
+    #[test]
+    fn wrong_reward() {
+        let (miner, sign_key) = create_signer();
+        let reward = RewardSchedule::default();
+        let mut ledger = Ledger::default();
+
+        let coinbase = Transaction::new_coinbase(&Hash::ZERO, &miner, 0, Amount::from_gry(100), &sign_key);
+        assert!(ledger.apply_block(&Block::new(Vec::new(), Vec::new(), coinbase, Hash::ZERO, 0, 0, Hash::ZERO, Vec::new()), &reward).is_err());
+        assert_eq!(ledger.balance_of(&miner), Amount::ZERO);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use crate::{net::serialize::Serializer, crypto::Hash};
+
+use super::{Block, BlockHeader, HeaderChain, ChainSpec, Transaction, MerkleProof, Amount};
+
+/// tx with the proof that it is part of the block `block_hash`
+#[derive(Clone, Serializer)]
+pub struct TxProof {
+    pub tx: Transaction,
+    pub block_hash: Hash,
+    pub proof: MerkleProof,
+}
+
+/// keeps only the headers and the proven txs of one key (instead of all blocks)
+pub struct LightClient {
+    pub_key: String,
+    /// id of the chain spec (txs are signed for it)
+    chain_id: Hash,
+    /// allocation of the key in the chain spec
+    genesis_balance: Amount,
+    headers: HeaderChain,
+    /// by block hash and tx hash (a tx can be in competing blocks)
+    txs: HashMap<(Hash, Hash), TxProof>,
+}
+
+impl LightClient {
+    pub fn new(pub_key: String, spec: &ChainSpec) -> LightClient {
+        let genesis_balance = spec.get_balances().iter()
+            .filter(|(allocated, _)| *allocated == pub_key)
+            .fold(0u64, |sum, (_, amount)| sum.saturating_add(amount.units()));
+
+        return LightClient {
+            pub_key,
+            chain_id: spec.gen_id(),
+            genesis_balance: Amount::from_units(genesis_balance),
+            headers: HeaderChain::new(spec),
+            txs: HashMap::new()
+        };
+    }
+
+    /// returns false if the parent of the header is unknown (see `HeaderChain::add_header`)
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<bool, &'static str> {
+        return self.headers.add_header(header);
+    }
+
+    /// keeps the header and the own txs of the block, returns false if its parent is unknown
+    pub fn add_block(&mut self, block: &Block) -> Result<bool, &'static str> {
+        if !block.verify_merkle_root() {
+            return Err("merkle root does not match txs");
+        }
+
+        let linked = self.headers.add_header(block.get_header())?;
+        for proof in block.get_proofs_of(&self.pub_key) {
+            self.txs.insert((proof.block_hash, proof.tx.gen_hash()), proof);
+        }
+
+        return Ok(linked);
+    }
+
+    /// the block of the tx has to be in the header chain already
+    pub fn add_proof(&mut self, proof: TxProof) -> Result<(), &'static str> {
+        if proof.tx.payer != self.pub_key && proof.tx.payee != self.pub_key {
+            return Err("tx does not involve this key");
+        }
+
+        if !proof.tx.verify(&self.chain_id) {
+            return Err("tx is not signed by its payer");
+        }
+
+        if !self.headers.verify_merkle_proof(&proof.block_hash, &proof.tx, &proof.proof) {
+            return Err("tx is not part of the header chain");
+        }
+
+        self.txs.insert((proof.block_hash, proof.tx.gen_hash()), proof);
+        return Ok(());
+    }
+
+    /// proven txs of blocks in the main chain (txs of stale blocks do not count)
+    pub fn get_txs(&self) -> Vec<&Transaction> {
+        return self.txs.values()
+            .filter(|proof| self.headers.is_in_chain(&proof.block_hash))
+            .map(|proof| &proof.tx)
+            .collect();
+    }
+
+    /// ids of the proven txs of the main chain
+    pub fn get_tx_ids(&self) -> Vec<Hash> {
+        return self.get_txs().iter().map(|tx| tx.gen_hash()).collect();
+    }
+
+    /// proven tx of the main chain with its block hash and proof
+    pub fn get_proof(&self, tx_id: &Hash) -> Option<&TxProof> {
+        return self.txs.values()
+            .find(|proof| proof.tx.gen_hash() == *tx_id && self.headers.is_in_chain(&proof.block_hash));
+    }
+
+    /// allocation of the chain spec plus the proven txs
+    pub fn get_balance(&self) -> Amount {
+        let (mut received, mut sent) = (self.genesis_balance.units(), 0u64);
+        for tx in self.get_txs() {
+            if tx.payee == self.pub_key { received = received.saturating_add(tx.amount.units()); }
+            if tx.payer == self.pub_key { sent = sent.saturating_add(tx.amount.units()).saturating_add(tx.fee.units()); }
+        }
+
+        return Amount::from_units(received.saturating_sub(sent));
+    }
+
+    /// lowest seq the next tx of this key may have (as far as the proven txs tell)
+    pub fn get_next_seq(&self) -> u64 {
+        return self.get_txs().iter()
+            .filter(|tx| tx.payer == self.pub_key)
+            .map(|tx| tx.seq.saturating_add(1))
+            .max()
+            .unwrap_or(0);
+    }
+
+    pub fn get_headers(&self) -> &HeaderChain {
+        return &self.headers;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        blockchain::{Blockchain, Block, Transaction, ChainSpec, Allocation, Amount},
+        net::sync::{ProofsReq, ProofsRes}, test_utils::{create_signer, solve}
+    };
+
+    use super::LightClient;
+
+    #[test]
+    fn proofs_against_headers() {
+        let (payer, sign_key) = create_signer();
+        let payee = "light".to_string();
+
+        let mut spec = ChainSpec { allocations: vec![Allocation { pub_key: payee.clone(), amount: Amount::from_gry(5) }], ..ChainSpec::default() };
+        spec.difficulty.pow_limit_bits = 8;
+        let mut blockchain = Blockchain::new(&spec);
+        let chain_id = *blockchain.get_chain_id();
+
+        for round in 1..4 {
+            let (prev_hash, target) = (blockchain.get_cur_hash(), blockchain.get_next_target());
+            let txs = if round == 2 { vec![Transaction::new(&chain_id, &payer, &payee, 0, Amount::from_gry(10), Amount::ZERO, &sign_key)] } else { Vec::new() };
+            let coinbase = Transaction::new_coinbase(&chain_id, &payer, round, blockchain.get_reward(round), &sign_key);
+            let timestamp = Block::gen_timestamp();
+            let header_hash = Block::gen_header_hash(prev_hash, round, timestamp, target, Block::gen_merkle_root(&txs, &[], &coinbase));
+            let seal = solve(&header_hash, &target);
+
+            blockchain.add_block(&Block::new(txs, Vec::new(), coinbase, prev_hash, round, timestamp, target, seal));
+        }
+        assert_eq!(blockchain.get_round(), 4);
+
+        let mut light = LightClient::new(payee.clone(), &spec);
+        assert_eq!(light.get_balance(), Amount::from_gry(5));
+        let proofs = blockchain.get_proofs_of(&payee, 0, usize::MAX);
+        assert_eq!(proofs.len(), 1);
+        assert!(light.add_proof(proofs[0].clone()).is_err());
+
+        // headers out of order wait for their parents
+        let blocks = blockchain.get_blocks(0, 10);
+        assert_eq!(light.add_header(blocks[3].get_header()), Ok(false));
+        assert_eq!(light.add_header(blocks[2].get_header()), Ok(false));
+        assert_eq!(light.get_headers().get_round(), 1);
+        for block in &blocks {
+            assert_eq!(light.add_header(block.get_header()), Ok(true));
+        }
+        assert_eq!(light.get_headers().get_cur_hash(), blockchain.get_cur_hash());
+
+        let mut moved = proofs[0].clone();
+        moved.block_hash = blockchain.get_cur_hash();
+        assert!(light.add_proof(moved).is_err());
+        assert!(light.add_proof(blockchain.get_proofs_of(&payer, 0, 1)[0].clone()).is_err());
+
+        light.add_proof(proofs[0].clone()).unwrap();
+        assert_eq!(light.get_balance(), Amount::from_gry(15));
+        assert_eq!(light.get_balance(), blockchain.balance_of(&payee));
+
+        // proofs are handed out in pages until a page comes back empty
+        let mut req = Some(ProofsReq { pub_key: payer.clone(), from: 0 });
+        let mut pages = Vec::<usize>::new();
+        while let Some(cur_req) = req {
+            let res = ProofsRes::new(&cur_req, &blockchain, 3000);
+            pages.push(res.proofs.len());
+            req = res.next_req(&payer);
+        }
+        assert!(pages.len() > 2);
+        assert_eq!(pages.iter().sum::<usize>(), blockchain.get_proofs_of(&payer, 0, usize::MAX).len());
+    }
+}
@@ -0,0 +1,257 @@
+use std::{collections::{HashMap, HashSet, BTreeSet, BinaryHeap}, cmp::Reverse};
+
+use crate::crypto::Hash;
+
+use super::{Transaction, Blockchain, Block, Amount};
+
+/// max number of pending txs (the ones with the lowest fee rate are evicted first)
+pub const MAX_MEMPOOL_TXS: usize = 10_000;
+/// pending txs older than this are dropped (in micro secs)
+pub const MAX_TX_AGE: u128 = 60 * 60 * 1_000_000;
+
+struct Entry {
+    tx: Transaction,
+    /// when the tx was added (in micro secs)
+    added: u128,
+}
+
+/// pending txs which are valid on top of the current chain
+pub struct Mempool {
+    /// by tx hash
+    txs: HashMap<Hash, Entry>,
+    /// tx hashes by payer
+    by_payer: HashMap<String, HashSet<Hash>>,
+    /// (added, tx hash) ordered oldest first
+    by_age: BTreeSet<(u128, Hash)>,
+    /// (fee rate, added, tx hash) ordered lowest fee rate first (and newest first for equal rates)
+    by_fee: BTreeSet<(u64, Reverse<u128>, Hash)>,
+    max_txs: usize,
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        return Self::with_max_txs(MAX_MEMPOOL_TXS);
+    }
+
+    pub fn with_max_txs(max_txs: usize) -> Mempool {
+        return Mempool { txs: HashMap::new(), by_payer: HashMap::new(), by_age: BTreeSet::new(), by_fee: BTreeSet::new(), max_txs };
+    }
+
+    /// validates the tx against the chain and the other pending txs of its payer
+    /// returns false if the tx is already known (or its seq is already used by the chain)
+    pub fn add(&mut self, tx: Transaction, blockchain: &Blockchain) -> Result<bool, &'static str> {
+        if tx.is_coinbase() {
+            return Err("unexpected coinbase");
+        }
+
+        let hash = tx.gen_hash();
+        if self.txs.contains_key(&hash) || tx.seq < blockchain.get_next_seq(&tx.payer) {
+            return Ok(false);
+        }
+
+        if self.get_txs_of(&tx.payer).iter().any(|pending| pending.seq == tx.seq) {
+            return Err("seq is already pending");
+        }
+
+        if tx.amount.is_zero() {
+            return Err("invalid amount");
+        }
+
+        if !tx.verify(blockchain.get_chain_id()) {
+            return Err("tx is not signed by its payer");
+        }
+
+        let cost = tx.get_cost().ok_or("fee overflows")?;
+        let pending = self.get_pending_of(&tx.payer).checked_add(cost).ok_or("tx would overdraw its payer")?;
+        if pending > blockchain.balance_of(&tx.payer) {
+            return Err("tx would overdraw its payer");
+        }
+
+        let now = Block::gen_timestamp();
+        let fee_rate = tx.get_fee_rate();
+        self.expire(now);
+        if self.txs.len() >= self.max_txs {
+            self.evict(fee_rate)?;
+        }
+
+        self.by_payer.entry(tx.payer.clone()).or_default().insert(hash);
+        self.by_age.insert((now, hash));
+        self.by_fee.insert((fee_rate, Reverse(now), hash));
+        self.txs.insert(hash, Entry { tx, added: now });
+        return Ok(true);
+    }
+
+    /// drops expired txs and the ones which are confirmed (or not valid anymore) on top of the current chain
+    pub fn update(&mut self, blockchain: &Blockchain) {
+        self.expire(Block::gen_timestamp());
+
+        let mut entries = self.txs.iter().collect::<Vec<(&Hash, &Entry)>>();
+        entries.sort_by_key(|(_, entry)| (entry.tx.seq, entry.added));
+
+        let mut ledger = blockchain.get_ledger().clone();
+        let invalid = entries.into_iter()
+            .filter(|(_, entry)| ledger.apply(&entry.tx).is_err())
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<Hash>>();
+
+        for hash in invalid {
+            self.remove(&hash);
+        }
+    }
+
+    /// highest fee rate first, but the txs of each payer in the order of their seqs
+    /// (the txs are valid together as long as the pool is up to date)
+    pub fn select(&self, max: usize) -> Vec<Transaction> {
+        // pending txs of each payer, highest seq first (so the next one is the last)
+        let mut queues = self.by_payer.values()
+            .map(|hashes| {
+                let mut entries = hashes.iter().map(|hash| &self.txs[hash]).collect::<Vec<&Entry>>();
+                entries.sort_by_key(|entry| Reverse(entry.tx.seq));
+                entries
+            })
+            .collect::<Vec<Vec<&Entry>>>();
+
+        let mut heads = queues.iter().enumerate()
+            .filter_map(|(i, queue)| queue.last().map(|entry| (entry.tx.get_fee_rate(), Reverse(entry.added), i)))
+            .collect::<BinaryHeap<(u64, Reverse<u128>, usize)>>();
+
+        let mut txs = Vec::<Transaction>::new();
+        while txs.len() < max {
+            let Some((_, _, i)) = heads.pop() else {
+                break;
+            };
+
+            let entry = queues[i].pop().unwrap();
+            txs.push(entry.tx.clone());
+
+            if let Some(next) = queues[i].last() {
+                heads.push((next.tx.get_fee_rate(), Reverse(next.added), i));
+            }
+        }
+
+        return txs;
+    }
+
+    pub fn remove(&mut self, hash: &Hash) -> Option<Transaction> {
+        let entry = self.txs.remove(hash)?;
+        self.by_age.remove(&(entry.added, *hash));
+        self.by_fee.remove(&(entry.tx.get_fee_rate(), Reverse(entry.added), *hash));
+
+        if let Some(hashes) = self.by_payer.get_mut(&entry.tx.payer) {
+            hashes.remove(hash);
+            if hashes.is_empty() {
+                self.by_payer.remove(&entry.tx.payer);
+            }
+        }
+
+        return Some(entry.tx);
+    }
+
+    pub fn get_txs_of(&self, payer: &str) -> Vec<&Transaction> {
+        return self.by_payer.get(payer)
+            .map_or(Vec::new(), |hashes| hashes.iter().map(|hash| &self.txs[hash].tx).collect());
+    }
+
+    /// sum of all pending txs of the payer (fees included)
+    pub fn get_pending_of(&self, payer: &str) -> Amount {
+        return self.get_txs_of(payer).iter()
+            .try_fold(Amount::ZERO, |sum, tx| sum.checked_add(tx.get_cost()?))
+            .unwrap_or(Amount::from_units(u64::MAX));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.txs.is_empty();
+    }
+
+    fn expire(&mut self, now: u128) {
+        while let Some(&(added, hash)) = self.by_age.first() {
+            if now.saturating_sub(added) <= MAX_TX_AGE {
+                break;
+            }
+            self.remove(&hash);
+        }
+    }
+
+    /// makes room for a tx with `fee_rate` (fails if every pending tx pays at least as much)
+    fn evict(&mut self, fee_rate: u64) -> Result<(), &'static str> {
+        let Some(&(lowest, _, hash)) = self.by_fee.first() else {
+            return Ok(());
+        };
+
+        if lowest >= fee_rate {
+            return Err("mempool is full");
+        }
+
+        self.remove(&hash);
+        return Ok(());
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{pss::BlindedSigningKey, sha2::Sha256};
+
+    use crate::{blockchain::{Blockchain, Block, Transaction, Amount, ChainSpec}, test_utils::{create_signer, solve}};
+
+    use super::Mempool;
+
+    fn mine(txs: Vec<Transaction>, miner: &String, sign_key: &BlindedSigningKey<Sha256>, blockchain: &mut Blockchain) {
+        let (round, prev_hash, target) = (blockchain.get_round(), blockchain.get_cur_hash(), blockchain.get_next_target());
+        let reward = blockchain.get_reward(round).checked_add(Block::gen_fees(&txs).unwrap()).unwrap();
+        let coinbase = Transaction::new_coinbase(blockchain.get_chain_id(), miner, round, reward, sign_key);
+        let timestamp = Block::gen_timestamp();
+        let header_hash = Block::gen_header_hash(prev_hash, round, timestamp, target, Block::gen_merkle_root(&txs, &[], &coinbase));
+        let seal = solve(&header_hash, &target);
+
+        blockchain.add_block(&Block::new(txs, Vec::new(), coinbase, prev_hash, round, timestamp, target, seal));
+    }
+
+    #[test]
+    fn validate_dedup_and_evict() {
+        let (payer, sign_key) = create_signer();
+        let payee = "payee".to_string();
+        let mut blockchain = Blockchain::new(&ChainSpec::default());
+        let chain_id = *blockchain.get_chain_id();
+        mine(Vec::new(), &payer, &sign_key, &mut blockchain);
+
+        let mut mempool = Mempool::with_max_txs(2);
+        let txs = (0..3).map(|i| Transaction::new(&chain_id, &payer, &payee, i, Amount::from_gry(20), Amount::from_units((i+1) * 1000), &sign_key)).collect::<Vec<Transaction>>();
+        assert_eq!(mempool.add(txs[0].clone(), &blockchain), Ok(true));
+        assert_eq!(mempool.add(txs[0].clone(), &blockchain), Ok(false));
+        assert_eq!(mempool.add(txs[1].clone(), &blockchain), Ok(true));
+        assert_eq!(mempool.get_pending_of(&payer), Amount::from_units(Amount::from_gry(40).units() + 3000));
+
+        assert!(mempool.add(txs[2].clone(), &blockchain).is_err());
+        assert!(mempool.add(Transaction::new(&chain_id, &payee, &payer, 0, Amount::from_gry(1), Amount::ZERO, &sign_key), &blockchain).is_err());
+
+        // full, so only a higher fee rate makes room (by evicting the lowest one)
+        let cheap = Transaction::new(&chain_id, &payer, &payee, 3, Amount::from_gry(5), Amount::ZERO, &sign_key);
+        assert_eq!(mempool.add(cheap, &blockchain), Err("mempool is full"));
+
+        let small = Transaction::new(&chain_id, &payer, &payee, 3, Amount::from_gry(5), Amount::from_units(5000), &sign_key);
+        assert_eq!(mempool.add(small.clone(), &blockchain), Ok(true));
+        assert_eq!(mempool.add(Transaction::new(&chain_id, &payer, &payee, 3, Amount::from_gry(1), Amount::from_units(9000), &sign_key), &blockchain), Err("seq is already pending"));
+
+        // the txs of a payer are picked in the order of their seqs (even if a later one pays more)
+        assert!(mempool.select(10) == vec![txs[1].clone(), small.clone()]);
+        assert!(mempool.select(1) == vec![txs[1].clone()]);
+
+        // confirmed txs leave the pool (the payer mined the block, so it gets the fee back)
+        mine(vec![txs[1].clone()], &payer, &sign_key, &mut blockchain);
+        assert_eq!(blockchain.balance_of(&payer), Amount::from_gry(80));
+        assert_eq!(blockchain.balance_of(&payee), Amount::from_gry(20));
+
+        mempool.update(&blockchain);
+        assert!(mempool.select(10) == vec![small]);
+
+        // replays (and older seqs) are ignored
+        assert_eq!(mempool.add(txs[1].clone(), &blockchain), Ok(false));
+        assert_eq!(mempool.add(txs[0].clone(), &blockchain), Ok(false));
+    }
+}
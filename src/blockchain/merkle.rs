@@ -0,0 +1,115 @@
+use crate::{net::serialize::Serializer, crypto::{Hash, HASH_BYTES}};
+
+/// merkle root of the tx hashes (the last node of an odd level is paired with itself)
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::ZERO;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| hash_nodes(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+
+    return level[0];
+}
+
+fn hash_nodes(left: &Hash, right: &Hash) -> Hash {
+    let mut bytes = [0u8; 1 + 2*HASH_BYTES];
+    bytes[0] = 0x1;     // inner nodes can never be mistaken for tx hashes
+    bytes[1..1+HASH_BYTES].copy_from_slice(&left.0);
+    bytes[1+HASH_BYTES..].copy_from_slice(&right.0);
+
+    return Hash::digest(&bytes);
+}
+
+/// inclusion proof of a single leaf (siblings from the bottom to the top of the tree)
+#[derive(Clone, Debug, Serializer)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    pub fn new(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+        if index >= leaves.len() {
+            return None;
+        }
+
+        let mut siblings = Vec::<Hash>::new();
+        let mut level = leaves.to_vec();
+        let mut i = index;
+
+        while level.len() > 1 {
+            let sibling = if i.is_multiple_of(2) { (i+1).min(level.len()-1) } else { i-1 };
+            siblings.push(level[sibling]);
+
+            level = level.chunks(2)
+                .map(|pair| hash_nodes(&pair[0], pair.last().unwrap()))
+                .collect();
+            i /= 2;
+        }
+
+        return Some(MerkleProof { index, siblings });
+    }
+
+    pub fn verify(&self, leaf: &Hash, merkle_root: &Hash) -> bool {
+        let mut hash = *leaf;
+        let mut i = self.index;
+
+        for sibling in &self.siblings {
+            hash = if i.is_multiple_of(2) { hash_nodes(&hash, sibling) } else { hash_nodes(sibling, &hash) };
+            i /= 2;
+        }
+
+        return i == 0 && &hash == merkle_root;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::Hash;
+
+    use super::{merkle_root, MerkleProof};
+
+    fn leaves(count: u8) -> Vec<Hash> {
+        return (0..count).map(|i| Hash::digest(&[i])).collect();
+    }
+
+    #[test]
+    fn proofs() {
+        for count in 1..10 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = MerkleProof::new(&leaves, i).unwrap();
+                assert!(proof.verify(leaf, &root));
+                assert!(!proof.verify(&Hash::digest(b"other tx"), &root));
+            }
+
+            assert!(MerkleProof::new(&leaves, leaves.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn wrong_index() {
+        let leaves = leaves(4);
+        let root = merkle_root(&leaves);
+
+        let mut proof = MerkleProof::new(&leaves, 1).unwrap();
+        proof.index = 0;
+        assert!(!proof.verify(&leaves[1], &root));
+
+        proof.index = 5;
+        assert!(!proof.verify(&leaves[1], &root));
+    }
+
+    #[test]
+    fn single_leaf_is_root() {
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+    }
+}
@@ -1,53 +1,154 @@
 use std::{
+    fmt::Display,
     thread::{JoinHandle, spawn},
-    sync::{mpsc::{Receiver, Sender, channel}, Arc, Mutex},
-    hash::{Hash, Hasher},
-    collections::{hash_map::DefaultHasher, VecDeque}
+    sync::{mpsc::{Receiver, Sender, channel}, Arc, Mutex, Condvar, atomic::{AtomicU64, AtomicBool, Ordering}},
+    time::Instant,
 };
 
-use rand::random;
+use rsa::{pss::BlindedSigningKey, sha2::Sha256};
 
-use super::Transaction;
+use crate::crypto::Hash;
 
-const DIFFICULTY: u64 = u64::MAX >> 20;
+use super::{Transaction, Blockchain, Block, Consensus, Mempool, DoubleSign, MAX_BLOCK_TXS, MAX_BLOCK_EVIDENCE};
+
+/// block the miner is currently searching a seal for
+struct Job {
+    id: u64,
+    txs: Vec<Transaction>,
+    evidence: Vec<DoubleSign>,
+    coinbase: Transaction,
+    prev_hash: Hash,
+    round: usize,
+    timestamp: u128,
+    target: Hash,
+    /// job was started by `mine_reward` (is queued again if it gets cancelled)
+    is_reward_job: bool,
+}
+
+/// what the workers seal
+struct Work {
+    job_id: u64,
+    header_hash: Hash,
+    target: Hash,
+    consensus: Arc<dyn Consensus>,
+}
+
+/// state shared between the miner and its workers
+struct Shared {
+    work: Mutex<Option<Work>>,
+    new_work: Condvar,
+    /// id of the job which is searched for (0 if there is none), workers stop as soon as it changes
+    job_id: AtomicU64,
+    online: AtomicBool,
+    sign_key: BlindedSigningKey<Sha256>,
+    attempts: AtomicU64,
+    /// time spent hashing summed over all workers (in micro secs)
+    busy_time: AtomicU64,
+}
+
+/// hashes tried so far and the resulting speed
+#[derive(Clone, Copy, Debug)]
+pub struct MinerStats {
+    pub attempts: u64,
+    /// hashes per second of all workers together
+    pub hashrate: f64,
+}
+
+impl Display for MinerStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{} hashes ({:.0} hashes/s)", self.attempts, self.hashrate);
+    }
+}
 
 pub struct Miner {
-    queue: VecDeque<(Transaction, usize)>,
-    send_req: Sender<u64>,
-    recv_res: Receiver<u64>,
-    online: Arc<Mutex<bool>>,
-    thread: JoinHandle<()>,
+    pub_key: String,
+    sign_key: BlindedSigningKey<Sha256>,
+    mempool: Mempool,
+    /// double signs which are not in the chain yet
+    evidence: Vec<DoubleSign>,
+    job: Option<Job>,
+    next_job_id: u64,
+    reward_jobs: usize,
+    shared: Arc<Shared>,
+    recv_res: Receiver<(u64, Vec<u8>)>,
+    workers: Vec<JoinHandle<()>>,
 }
 
 impl Miner {
-    pub fn new() -> Miner {
-        let (send_req, recv_req) = channel::<u64>();
-        let (send_res, recv_res) = channel::<u64>();
-        let queue = VecDeque::<(Transaction, usize)>::new();
+    /// starts `threads` workers (at least one), which sleep while there is no job
+    /// (the consensus engine of the blockchain decides how blocks are sealed)
+    pub fn new(pub_key: String, sign_key: BlindedSigningKey<Sha256>, threads: usize) -> Miner {
+        let (send_res, recv_res) = channel::<(u64, Vec<u8>)>();
+        let mempool = Mempool::new();
+
+        let shared = Arc::new(Shared {
+            work: Mutex::new(None),
+            new_work: Condvar::new(),
+            job_id: AtomicU64::new(0),
+            online: AtomicBool::new(true),
+            sign_key: sign_key.clone(),
+            attempts: AtomicU64::new(0),
+            busy_time: AtomicU64::new(0),
+        });
+
+        let workers = (0..threads.max(1))
+            .map(|_| Self::create_worker(Arc::clone(&shared), send_res.clone()))
+            .collect();
+        return Miner { pub_key, sign_key, mempool, evidence: Vec::new(), job: None, next_job_id: 1, reward_jobs: 0, shared, recv_res, workers }
+    }
+
+    pub fn add_tx(&mut self, tx: Transaction, blockchain: &Blockchain) {
+        let id = tx.gen_hash();
+        match self.mempool.add(tx, blockchain) {
+            Ok(true) => self.update(blockchain),
+            Ok(false) => {}
+            Err(err) => eprintln!("ERROR: tx {} rejected: {}", id, err),
+        }
+    }
+
+    /// the evidence goes into the next block of the miner (and slashes the validator)
+    pub fn add_evidence(&mut self, evidence: DoubleSign, blockchain: &Blockchain) {
+        if let Err(err) = evidence.check() {
+            eprintln!("ERROR: double sign evidence rejected: {}", err);
+            return;
+        }
 
-        let online = Arc::new(Mutex::new(true));
+        if blockchain.get_ledger().is_slashed(&evidence.validator) || self.evidence.iter().any(|e| e.validator == evidence.validator) {
+            return;
+        }
+
+        self.evidence.push(evidence);
+        self.update(blockchain);
+    }
 
-        let thread = Self::create_thread(Arc::clone(&online), recv_req, send_res);
-        return Miner { queue, send_req, recv_res, online, thread }
+    /// mines a block even if there are no txs (only for the block reward)
+    /// engines with turns only do it if the next block is the miner's turn
+    pub fn mine_reward(&mut self, blockchain: &Blockchain) {
+        self.reward_jobs += 1;
+        self.update(blockchain);
     }
 
-    pub fn add_tx(&mut self, tx: Transaction, round: usize) {
-        for (t, _) in &self.queue {
-            if t == &tx { return; }
+    /// drops confirmed (or not anymore valid) txs, cancels the job if the tip changed
+    /// and starts the next job if the miner is free (and may seal the next block)
+    pub fn update(&mut self, blockchain: &Blockchain) {
+        self.mempool.update(blockchain);
+        self.evidence.retain(|evidence| !blockchain.get_ledger().is_slashed(&evidence.validator));
+
+        if self.job.as_ref().is_some_and(|job| job.prev_hash != blockchain.get_cur_hash()) {
+            self.cancel_job();
         }
 
-        let nonce = tx.gen_nonce();
-        self.queue.push_back((tx, round));
-        if self.send_req.send(nonce).is_err() {
-            self.queue.pop_back();
-            eprintln!("ERROR: could not add tx to miner");
+        if self.job.is_none() && (!self.mempool.is_empty() || !self.evidence.is_empty() || self.reward_jobs > 0) {
+            self.start_job(blockchain);
         }
     }
 
-    pub fn recv_solution(&mut self) -> Option<(Transaction, u64, usize)> {
-        if let Ok(solution) = self.recv_res.try_recv() {
-            if let Some((tx, round)) = self.queue.pop_front() {
-                return Some((tx, solution, round));
+    pub fn recv_solution(&mut self) -> Option<Block> {
+        while let Ok((job_id, seal)) = self.recv_res.try_recv() {
+            // seals of cancelled jobs are dropped
+            if self.job.as_ref().is_some_and(|job| job.id == job_id) {
+                let job = self.job.take().unwrap();
+                return Some(Block::new(job.txs, job.evidence, job.coinbase, job.prev_hash, job.round, job.timestamp, job.target, seal));
             }
         }
 
@@ -55,48 +156,181 @@ impl Miner {
     }
 
     pub fn shutdown(self) {
-        *self.online.lock().unwrap() = false;
-        self.thread.join().unwrap();
-    }
+        self.shared.online.store(false, Ordering::Release);
+        self.shared.job_id.store(0, Ordering::Release);
+        {
+            let _work = self.shared.work.lock().unwrap();
+            self.shared.new_work.notify_all();
+        }
 
-    pub fn gen_mining_hash(nonce: u64, solution: u64) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        nonce.hash(&mut hasher);
-        solution.hash(&mut hasher);
-        return hasher.finish();
+        for worker in self.workers {
+            worker.join().unwrap();
+        }
     }
 
     pub fn is_idling(&self) -> bool {
-        return self.queue.is_empty();
+        return self.mempool.is_empty() && self.evidence.is_empty() && self.job.is_none() && self.reward_jobs == 0;
+    }
+
+    pub fn get_stats(&self) -> MinerStats {
+        let attempts = self.shared.attempts.load(Ordering::Acquire);
+        let busy_secs = self.shared.busy_time.load(Ordering::Acquire) as f64 / 1_000_000.0;
+
+        // the workers hash in parallel, so the time of one worker is the busy time divided by their number
+        let hashrate = if busy_secs > 0.0 { attempts as f64 * self.workers.len() as f64 / busy_secs } else { 0.0 };
+        return MinerStats { attempts, hashrate };
+    }
+
+    fn start_job(&mut self, blockchain: &Blockchain) {
+        let round = blockchain.get_round();
+        let prev_hash = blockchain.get_cur_hash();
+        let consensus = blockchain.get_consensus();
+        if !consensus.can_seal(&self.pub_key, &prev_hash, round, blockchain.get_ledger()) {
+            // the txs wait for the next turn, but nobody would seal the other blocks of the rewards
+            self.reward_jobs = 0;
+            return;
+        }
+
+        let txs = self.mempool.select(MAX_BLOCK_TXS);
+        let evidence = self.evidence.iter().take(MAX_BLOCK_EVIDENCE).cloned().collect::<Vec<DoubleSign>>();
+        let is_reward_job = self.reward_jobs > 0;
+        self.reward_jobs = self.reward_jobs.saturating_sub(1);
+
+        let timestamp = Block::gen_timestamp();
+        let target = blockchain.get_next_target();
+        let Some(reward) = Block::gen_fees(&txs).and_then(|fees| blockchain.get_reward(round).checked_add(fees)) else {
+            eprintln!("ERROR: fees of the mining job overflow");
+            return;
+        };
+        let coinbase = Transaction::new_coinbase(blockchain.get_chain_id(), &self.pub_key, round, reward, &self.sign_key);
+
+        let header_hash = Block::gen_header_hash(prev_hash, round, timestamp, target, Block::gen_merkle_root(&txs, &evidence, &coinbase));
+
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+
+        *self.shared.work.lock().unwrap() = Some(Work { job_id: id, header_hash, target, consensus: Arc::clone(consensus) });
+        self.shared.job_id.store(id, Ordering::Release);
+        self.shared.new_work.notify_all();
+
+        self.job = Some(Job { id, txs, evidence, coinbase, prev_hash, round, timestamp, target, is_reward_job });
+    }
+
+    /// stops the workers (the txs stay in the mempool)
+    fn cancel_job(&mut self) {
+        let Some(job) = self.job.take() else {
+            return;
+        };
+
+        if job.is_reward_job {
+            self.reward_jobs += 1;
+        }
+
+        self.shared.job_id.store(0, Ordering::Release);
+        *self.shared.work.lock().unwrap() = None;
+        println!("cancel mining job (round: {}): tip changed", job.round);
     }
 
-    fn create_thread(online: Arc<Mutex<bool>>, recv: Receiver<u64>, send: Sender<u64>) -> JoinHandle<()> {
+    fn create_worker(shared: Arc<Shared>, send: Sender<(u64, Vec<u8>)>) -> JoinHandle<()> {
         return spawn(move || {
+            let mut last_job_id = 0;
+
             loop {
-                if let Ok(nonce) = recv.try_recv() {
-                    let solution = Self::mine(nonce);
-                    if send.send(solution).is_err() {
-                        break;
+                let (job_id, header_hash, target, consensus) = {
+                    let mut work = shared.work.lock().unwrap();
+                    loop {
+                        if !shared.online.load(Ordering::Acquire) {
+                            return;
+                        }
+
+                        match work.as_ref() {
+                            Some(w) if w.job_id != last_job_id => break (w.job_id, w.header_hash, w.target, Arc::clone(&w.consensus)),
+                            _ => work = shared.new_work.wait(work).unwrap(),
+                        }
+                    }
+                };
+
+                last_job_id = job_id;
+                if let Some(seal) = Self::mine(&shared, job_id, &header_hash, &target, consensus.as_ref()) {
+                    if send.send((job_id, seal)).is_err() {
+                        return;
                     }
-                } else if !*online.lock().unwrap() {
-                    break;
                 }
             }
         });
     }
 
-    fn mine(nonce: u64) -> u64 {
-        let mut solution = random::<u64>();
+    /// searches until the block is sealed or the job is not current anymore
+    /// (only the first worker sealing the job returns its seal)
+    fn mine(shared: &Shared, job_id: u64, header_hash: &Hash, target: &Hash, consensus: &dyn Consensus) -> Option<Vec<u8>> {
+        let mut start = Instant::now();
+        let seal = consensus.seal(header_hash, target, &shared.sign_key, &mut |attempts| {
+            shared.attempts.fetch_add(attempts, Ordering::AcqRel);
+            shared.busy_time.fetch_add(start.elapsed().as_micros() as u64, Ordering::AcqRel);
+            start = Instant::now();
 
-        while !Self::verify(nonce, solution) {
-            solution = random::<u64>();
+            return shared.job_id.load(Ordering::Acquire) == job_id;
+        })?;
+
+        if shared.job_id.compare_exchange(job_id, 0, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return None;
+        }
+
+        println!("sealed block: {}", Block::gen_hash(header_hash, &seal));
+        return Some(seal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use crate::{blockchain::{Blockchain, Block, ChainSpec}, test_utils::create_signer};
+
+    use super::Miner;
+
+    fn wait_for_block(miner: &mut Miner) -> Block {
+        for _ in 0..500 {
+            if let Some(block) = miner.recv_solution() {
+                return block;
+            }
+            sleep(Duration::from_millis(10));
         }
 
-        println!("found solution: {}", Self::gen_mining_hash(nonce, solution));
-        return solution;
+        panic!("no solution found");
     }
 
-    fn verify(nonce: u64, solution: u64) -> bool {
-        return Self::gen_mining_hash(nonce, solution) < DIFFICULTY;
+    #[test]
+    fn cancel_when_tip_changes() {
+        let (pub_key_pem, sign_key) = create_signer();
+
+        let (mut easy, mut hard) = (ChainSpec::default(), ChainSpec::default());
+        easy.difficulty.pow_limit_bits = 4;
+        hard.difficulty.pow_limit_bits = 128;
+        let mut easy_chain = Blockchain::new(&easy);
+        let hard_chain = Blockchain::new(&hard);
+
+        let mut miner = Miner::new(pub_key_pem, sign_key, 2);
+        miner.mine_reward(&easy_chain);
+        let first = wait_for_block(&mut miner);
+        assert!(easy_chain.add_block(&first).is_empty());
+        assert_eq!(easy_chain.get_cur_hash(), first.hash);
+        assert!(miner.is_idling());
+
+        // never finds a solution for the hard chain, so the job only ends by the new tip
+        miner.mine_reward(&hard_chain);
+        sleep(Duration::from_millis(50));
+        assert!(miner.recv_solution().is_none());
+
+        miner.update(&easy_chain);
+        let second = wait_for_block(&mut miner);
+        assert_eq!(second.prev_hash, first.hash);
+        assert_eq!(second.round, 2);
+        assert!(miner.is_idling());
+
+        let stats = miner.get_stats();
+        assert!(stats.attempts > 0 && stats.hashrate > 0.0);
+
+        miner.shutdown();
     }
 }
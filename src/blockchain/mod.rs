@@ -1,9 +1,42 @@
 mod block;
+mod header;
+mod headers;
+mod tree;
+mod light;
 mod blockchain;
 mod transaction;
 mod miner;
+mod mempool;
+mod ledger;
+mod reward;
+mod merkle;
+mod amount;
+mod difficulty;
+mod consensus;
+mod pow;
+mod poa;
+mod pos;
+mod evidence;
+mod store;
+mod spec;
 
-pub use block::Block;
-pub use blockchain::Blockchain;
-pub use transaction::Transaction;
-pub use miner::Miner;
+pub use block::{Block, MAX_BLOCK_TXS, MAX_BLOCK_EVIDENCE};
+pub use header::BlockHeader;
+pub use headers::HeaderChain;
+pub use light::{LightClient, TxProof};
+pub use merkle::MerkleProof;
+pub use blockchain::{Blockchain, ChainError};
+pub use transaction::{Transaction, STAKE_PAYEE};
+pub use miner::{Miner, MinerStats};
+pub use mempool::Mempool;
+pub use ledger::Ledger;
+pub use reward::RewardSchedule;
+pub use amount::Amount;
+pub use difficulty::Difficulty;
+pub use consensus::Consensus;
+pub use pow::ProofOfWork;
+pub use poa::ProofOfAuthority;
+pub use pos::ProofOfStake;
+pub use evidence::DoubleSign;
+pub use store::BlockStore;
+pub use spec::{ChainSpec, ConsensusSpec, Allocation};
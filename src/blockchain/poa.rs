@@ -0,0 +1,92 @@
+use rsa::{pss::{BlindedSigningKey, Signature}, sha2::Sha256};
+
+use crate::crypto::Hash;
+
+use super::{BlockHeader, Consensus, Ledger, consensus::sign_header};
+
+/// the authorities take turns (by round) sealing blocks with their signature of the header
+pub struct ProofOfAuthority {
+    /// pub keys (pem) in the order of their turns
+    authorities: Vec<String>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(authorities: Vec<String>) -> ProofOfAuthority {
+        return ProofOfAuthority { authorities };
+    }
+
+    /// authority whose turn it is in `round`
+    pub fn get_authority(&self, round: usize) -> Option<&String> {
+        if self.authorities.is_empty() {
+            return None;
+        }
+
+        return self.authorities.get(round % self.authorities.len());
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    /// there is no work to be done, so every block has the same target
+    fn get_target(&self, _parent: Option<&BlockHeader>, _get_header: &dyn Fn(&Hash) -> BlockHeader) -> Hash {
+        return Hash::ZERO;
+    }
+
+    fn check_seal(&self, header: &BlockHeader) -> Result<(), &'static str> {
+        let authority = self.get_authority(header.round).ok_or("there are no authorities")?;
+        Signature::try_from(header.seal.as_slice()).map_err(|_| "seal is not a signature")?;
+
+        if !header.is_signed_by(authority) {
+            return Err("block is not sealed by the authority of its round");
+        }
+
+        return Ok(());
+    }
+
+    /// the authorities take turns, so the longest chain wins
+    fn get_weight(&self, _header: &BlockHeader) -> u128 {
+        return 1;
+    }
+
+    fn can_seal(&self, pub_key: &str, _prev_hash: &Hash, round: usize, _ledger: &Ledger) -> bool {
+        return self.get_authority(round).is_some_and(|authority| authority == pub_key);
+    }
+
+    fn seal(&self, header_hash: &Hash, _target: &Hash, sign_key: &BlindedSigningKey<Sha256>, progress: &mut dyn FnMut(u64) -> bool) -> Option<Vec<u8>> {
+        progress(1);
+        return Some(sign_header(header_hash, sign_key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{pss::BlindedSigningKey, sha2::Sha256};
+
+    use crate::{blockchain::{Block, Transaction, Consensus, Ledger, Amount}, crypto::Hash, test_utils::create_signer};
+
+    use super::ProofOfAuthority;
+
+    #[test]
+    fn authorities_take_turns() {
+        let keys = (0..2).map(|_| create_signer()).collect::<Vec<(String, BlindedSigningKey<Sha256>)>>();
+        let poa = ProofOfAuthority::new(keys.iter().map(|(pub_key, _)| pub_key.clone()).collect());
+
+        let can_seal = |pub_key: &str, round| poa.can_seal(pub_key, &Hash::ZERO, round, &Ledger::default());
+        assert!(can_seal(&keys[0].0, 0) && can_seal(&keys[1].0, 1) && can_seal(&keys[0].0, 2));
+        assert!(!can_seal(&keys[1].0, 0) && !can_seal("unknown", 1));
+
+        let seal_block = |round: usize, sign_key: &BlindedSigningKey<Sha256>| {
+            let coinbase = Transaction::new_coinbase(&Hash::ZERO, &keys[0].0, round, Amount::from_gry(50), sign_key);
+            let header_hash = Block::gen_header_hash(Hash::ZERO, round, 0, Hash::ZERO, Block::gen_merkle_root(&[], &[], &coinbase));
+            let seal = poa.seal(&header_hash, &Hash::ZERO, sign_key, &mut |_| true).unwrap();
+            Block::new(Vec::new(), Vec::new(), coinbase, Hash::ZERO, round, 0, Hash::ZERO, seal).get_header()
+        };
+
+        assert_eq!(poa.check_seal(&seal_block(1, &keys[1].1)), Ok(()));
+        assert!(poa.check_seal(&seal_block(1, &keys[0].1)).is_err());
+
+        // the signature only covers its own header
+        let mut moved = seal_block(0, &keys[0].1);
+        moved.round = 2;
+        assert!(poa.check_seal(&moved).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use rsa::{pss::{BlindedSigningKey, Signature}, sha2::Sha256};
+
+use crate::crypto::Hash;
+
+use super::{BlockHeader, Consensus, Ledger, Amount, DoubleSign, consensus::sign_header};
+
+/// the proposer of each slot is picked by stake from a seed every node derives the same way,
+/// validators caught double signing lose their stake
+pub struct ProofOfStake {
+    /// validators (and their stake) before anyone locked balance by a stake tx
+    genesis: Vec<(String, Amount)>,
+    /// the same for the whole chain (e.g. its id), the seeds of the slots are derived from it
+    seed: Hash,
+}
+
+impl ProofOfStake {
+    pub fn new(genesis: Vec<(String, Amount)>, seed: Hash) -> ProofOfStake {
+        return ProofOfStake { genesis, seed };
+    }
+
+    /// stakes of all validators which are not slashed (ordered by pub key)
+    pub fn get_validators(&self, ledger: &Ledger) -> Vec<(String, Amount)> {
+        let mut stakes = BTreeMap::<&String, u64>::new();
+        let genesis = self.genesis.iter().map(|(validator, stake)| (validator, *stake));
+        for (validator, stake) in genesis.chain(ledger.get_stakes().iter().map(|(validator, stake)| (validator, *stake))) {
+            let sum = stakes.entry(validator).or_default();
+            *sum = sum.saturating_add(stake.units());
+        }
+
+        return stakes.into_iter()
+            .filter(|(validator, stake)| *stake > 0 && !ledger.is_slashed(validator))
+            .map(|(validator, stake)| (validator.clone(), Amount::from_units(stake)))
+            .collect();
+    }
+
+    /// validator allowed to seal the block of `round` (None if nobody has stake)
+    pub fn get_proposer(&self, round: usize, ledger: &Ledger) -> Option<String> {
+        let validators = self.get_validators(ledger);
+        let total = validators.iter().map(|(_, stake)| stake.units() as u128).sum::<u128>();
+        if total == 0 {
+            return None;
+        }
+
+        let seed = self.gen_seed(round);
+        let mut pick = u128::from_be_bytes(seed.0[..16].try_into().unwrap()) % total;
+        for (validator, stake) in validators {
+            if pick < stake.units() as u128 {
+                return Some(validator);
+            }
+            pick -= stake.units() as u128;
+        }
+
+        return None;
+    }
+
+    /// seed of the slot of `round`, it does not depend on the blocks before (their seals are randomized signatures),
+    /// so a proposer can not try seals until it gets the next slot as well
+    fn gen_seed(&self, round: usize) -> Hash {
+        let mut bytes = self.seed.0.to_vec();
+        bytes.extend_from_slice(&(round as u64).to_le_bytes());
+
+        return Hash::digest(&bytes);
+    }
+}
+
+impl Consensus for ProofOfStake {
+    /// there is no work to be done, so every block has the same target
+    fn get_target(&self, _parent: Option<&BlockHeader>, _get_header: &dyn Fn(&Hash) -> BlockHeader) -> Hash {
+        return Hash::ZERO;
+    }
+
+    /// the signer can only be checked against the stakes (see `check_proposer`)
+    fn check_seal(&self, header: &BlockHeader) -> Result<(), &'static str> {
+        Signature::try_from(header.seal.as_slice()).map_err(|_| "seal is not a signature")?;
+        return Ok(());
+    }
+
+    fn check_proposer(&self, header: &BlockHeader, ledger: &Ledger) -> Result<(), &'static str> {
+        let proposer = self.get_proposer(header.round, ledger).ok_or("there are no validators")?;
+        if !header.is_signed_by(&proposer) {
+            return Err("block is not sealed by the proposer of its slot");
+        }
+
+        return Ok(());
+    }
+
+    /// one proposer per slot, so the longest chain wins
+    fn get_weight(&self, _header: &BlockHeader) -> u128 {
+        return 1;
+    }
+
+    /// light clients have no stakes to check the proposers against
+    fn checks_headers(&self) -> bool {
+        return false;
+    }
+
+    fn can_seal(&self, pub_key: &str, _prev_hash: &Hash, round: usize, ledger: &Ledger) -> bool {
+        return self.get_proposer(round, ledger).is_some_and(|proposer| proposer == pub_key);
+    }
+
+    fn seal(&self, header_hash: &Hash, _target: &Hash, sign_key: &BlindedSigningKey<Sha256>, progress: &mut dyn FnMut(u64) -> bool) -> Option<Vec<u8>> {
+        progress(1);
+        return Some(sign_header(header_hash, sign_key));
+    }
+
+    fn find_double_sign(&self, first: &BlockHeader, second: &BlockHeader, ledger: &Ledger) -> Option<DoubleSign> {
+        return self.get_validators(ledger).into_iter()
+            .map(|(validator, _)| DoubleSign::new(validator, first.clone(), second.clone()))
+            .find(|evidence| evidence.check().is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{pss::BlindedSigningKey, sha2::Sha256};
+
+    use crate::{blockchain::{Blockchain, HeaderChain, Block, Transaction, Consensus, DoubleSign, Amount, ChainSpec, ConsensusSpec, Allocation}, crypto::Hash, test_utils::create_signer};
+
+    use super::ProofOfStake;
+
+    /// block sealed by the proposer of the next slot
+    fn propose(blockchain: &Blockchain, keys: &[(String, BlindedSigningKey<Sha256>)], txs: Vec<Transaction>, evidence: Vec<DoubleSign>, timestamp: u128) -> Block {
+        let (round, prev_hash) = (blockchain.get_round(), blockchain.get_cur_hash());
+        let consensus = blockchain.get_consensus();
+        let (proposer, sign_key) = keys.iter().find(|(pub_key, _)| consensus.can_seal(pub_key, &prev_hash, round, blockchain.get_ledger())).unwrap();
+
+        return seal_block(blockchain, prev_hash, round, proposer, sign_key, txs, evidence, timestamp);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn seal_block(blockchain: &Blockchain, prev_hash: Hash, round: usize, proposer: &String, sign_key: &BlindedSigningKey<Sha256>,
+                  txs: Vec<Transaction>, evidence: Vec<DoubleSign>, timestamp: u128) -> Block {
+        let consensus = blockchain.get_consensus();
+        let reward = blockchain.get_reward(round).checked_add(Block::gen_fees(&txs).unwrap()).unwrap();
+        let coinbase = Transaction::new_coinbase(blockchain.get_chain_id(), proposer, round, reward, sign_key);
+        let header_hash = Block::gen_header_hash(prev_hash, round, timestamp, Hash::ZERO, Block::gen_merkle_root(&txs, &evidence, &coinbase));
+        let seal = consensus.seal(&header_hash, &Hash::ZERO, sign_key, &mut |_| true).unwrap();
+
+        return Block::new(txs, evidence, coinbase, prev_hash, round, timestamp, Hash::ZERO, seal);
+    }
+
+    #[test]
+    fn stake_and_slash() {
+        let keys = (0..2).map(|_| create_signer()).collect::<Vec<(String, BlindedSigningKey<Sha256>)>>();
+        let ((a, a_key), (b, b_key)) = (&keys[0], &keys[1]);
+
+        let spec = ChainSpec {
+            allocations: vec![Allocation { pub_key: a.clone(), amount: Amount::from_gry(30) }],
+            consensus: ConsensusSpec::Pos { validators: vec![Allocation { pub_key: a.clone(), amount: Amount::from_gry(1) }] },
+            ..ChainSpec::default()
+        };
+        let pos = ProofOfStake::new(vec![(a.clone(), Amount::from_gry(1))], spec.gen_id());
+        let mut blockchain = Blockchain::new(&spec);
+        let chain_id = *blockchain.get_chain_id();
+
+        // only the genesis validator proposes until b locks some of its balance
+        let pay = Transaction::new(&chain_id, a, b, 0, Amount::from_gry(20), Amount::ZERO, a_key);
+        let stake = Transaction::new_stake(&chain_id, b, 0, Amount::from_gry(10), Amount::ZERO, b_key);
+        for txs in [Vec::new(), vec![pay], vec![stake]] {
+            let block = propose(&blockchain, &keys, txs, Vec::new(), Block::gen_timestamp());
+            assert!(block.get_header().is_signed_by(a));
+            blockchain.add_block(&block);
+        }
+        assert_eq!(blockchain.get_round(), 4);
+        assert_eq!(blockchain.balance_of(b), Amount::from_gry(10));
+        let mut validators = vec![(a.clone(), Amount::from_gry(1)), (b.clone(), Amount::from_gry(10))];
+        validators.sort();
+        assert_eq!(pos.get_validators(blockchain.get_ledger()), validators);
+
+        // the proposers of the slots do not depend on the (randomized) seals before
+        let ledger = blockchain.get_ledger();
+        assert!((4..20).all(|round| pos.can_seal(&pos.get_proposer(round, ledger).unwrap(), &Hash::digest(&round.to_le_bytes()), round, ledger)));
+
+        // b had no stake in the first round, so its side branch is not even stored
+        let genesis_hash = blockchain.get_hashes()[0].1;
+        let side = seal_block(&blockchain, genesis_hash, 1, b, b_key, Vec::new(), Vec::new(), Block::gen_timestamp());
+        blockchain.add_block(&side);
+        assert!(!blockchain.contains(&side.hash));
+
+        // the proposer of the slot seals two blocks
+        let timestamp = Block::gen_timestamp();
+        let first = propose(&blockchain, &keys, Vec::new(), Vec::new(), timestamp);
+        let second = propose(&blockchain, &keys, Vec::new(), Vec::new(), timestamp + 1);
+        let (cheater, cheater_key) = keys.iter().find(|(pub_key, _)| first.get_header().is_signed_by(pub_key)).unwrap();
+        blockchain.add_block(&first);
+        assert!(blockchain.find_double_sign(&first).is_none());
+        blockchain.add_block(&second);
+
+        let evidence = blockchain.find_double_sign(&second).unwrap();
+        assert_eq!(&evidence.validator, cheater);
+
+        let block = propose(&blockchain, &keys, Vec::new(), vec![evidence.clone()], Block::gen_timestamp());
+        blockchain.add_block(&block);
+        assert_eq!(blockchain.get_cur_hash(), block.hash);
+        assert!(blockchain.get_ledger().is_slashed(cheater));
+        assert_eq!(blockchain.get_ledger().stake_of(cheater), Amount::ZERO);
+        assert!(pos.get_validators(blockchain.get_ledger()).iter().all(|(validator, _)| validator != cheater));
+
+        // the evidence counts only once
+        let again = propose(&blockchain, &keys, Vec::new(), vec![evidence], Block::gen_timestamp());
+        blockchain.add_block(&again);
+        assert_eq!(blockchain.get_cur_hash(), block.hash);
+
+        // the slashed validator does not propose anymore
+        let mut ledger = blockchain.get_ledger().clone();
+        assert!(!pos.can_seal(cheater, &blockchain.get_cur_hash(), blockchain.get_round(), &ledger));
+        assert_eq!(ledger.apply(&Transaction::new_stake(&chain_id, cheater, 5, Amount::from_gry(1), Amount::ZERO, cheater_key)), Err("validator is slashed"));
+        assert_eq!(blockchain.validate(), Ok(()));
+
+        // light clients can not check the proposers
+        let mut headers = HeaderChain::new(&spec);
+        assert!(headers.add_header(blockchain.get_blocks(1, 1)[0].get_header()).is_err());
+    }
+}
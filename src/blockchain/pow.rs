@@ -0,0 +1,66 @@
+use rand::random;
+use rsa::{pss::BlindedSigningKey, sha2::Sha256};
+
+use crate::crypto::Hash;
+
+use super::{Block, BlockHeader, Consensus, Difficulty, Ledger};
+
+/// hashes tried before the progress is reported (and the search can be cancelled)
+const BATCH_SIZE: u64 = 1024;
+
+/// the seal is a solution whose hash is below the target, the branch with the most work wins
+#[derive(Clone, Copy, Default)]
+pub struct ProofOfWork {
+    pub difficulty: Difficulty,
+}
+
+impl ProofOfWork {
+    pub fn gen_mining_hash(header_hash: &Hash, solution: u64) -> Hash {
+        return Block::gen_hash(header_hash, &solution.to_le_bytes());
+    }
+}
+
+impl Consensus for ProofOfWork {
+    fn get_target(&self, parent: Option<&BlockHeader>, get_header: &dyn Fn(&Hash) -> BlockHeader) -> Hash {
+        return self.difficulty.get_target(parent, get_header);
+    }
+
+    fn check_seal(&self, header: &BlockHeader) -> Result<(), &'static str> {
+        if header.seal.len() != size_of::<u64>() {
+            return Err("seal is not a solution");
+        }
+
+        if header.hash >= header.target {
+            return Err("hash does not meet its target");
+        }
+
+        return Ok(());
+    }
+
+    /// expected number of hashes it took to mine the block
+    fn get_weight(&self, header: &BlockHeader) -> u128 {
+        return header.target.work();
+    }
+
+    fn can_seal(&self, _pub_key: &str, _prev_hash: &Hash, _round: usize, _ledger: &Ledger) -> bool {
+        return true;
+    }
+
+    fn seal(&self, header_hash: &Hash, target: &Hash, _sign_key: &BlindedSigningKey<Sha256>, progress: &mut dyn FnMut(u64) -> bool) -> Option<Vec<u8>> {
+        let mut solution = random::<u64>();
+
+        loop {
+            for attempts in 1..=BATCH_SIZE {
+                if Self::gen_mining_hash(header_hash, solution) < *target {
+                    progress(attempts);
+                    return Some(solution.to_le_bytes().to_vec());
+                }
+                solution = solution.wrapping_add(1);
+            }
+
+            if !progress(BATCH_SIZE) {
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Serialize, Deserialize};
+
+use super::Amount;
+
+/// block subsidy paid to the miner by the coinbase transaction
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardSchedule {
+    pub subsidy: Amount,
+    /// subsidy is halved every `halving_interval` rounds (never if it is 0)
+    pub halving_interval: usize,
+}
+
+impl RewardSchedule {
+    pub fn get_reward(&self, round: usize) -> Amount {
+        let halvings = round.checked_div(self.halving_interval).unwrap_or(0);
+        if halvings >= 64 {
+            return Amount::ZERO;
+        }
+
+        return Amount::from_units(self.subsidy.units() >> halvings);
+    }
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        return RewardSchedule { subsidy: Amount::from_gry(50), halving_interval: 210 };
+    }
+}
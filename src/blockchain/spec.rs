@@ -0,0 +1,244 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{net::{tcp::DEFAULT_MAGIC, pkg::{DEFAULT_MAX_PKG_SIZE, MIN_PKG_SIZE}}, crypto::Hash};
+
+use super::{Block, BlockHeader, Consensus, Difficulty, RewardSchedule, Amount, ProofOfWork, ProofOfAuthority, ProofOfStake};
+
+/// everything the nodes of a chain have to agree on (from a toml or json file or built in code)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainSpec {
+    pub name: String,
+    /// first 4 bytes of every frame, nodes with another magic can not talk to each other
+    pub magic: u32,
+    /// timestamp of the genesis block (in micro secs)
+    pub genesis_time: u64,
+    /// biggest package the nodes send or receive (bigger frames drop the connection)
+    #[serde(default = "default_max_pkg_size")]
+    pub max_pkg_size: usize,
+    /// balances of the genesis ledger
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,
+    #[serde(default)]
+    pub difficulty: DifficultySpec,
+    #[serde(default)]
+    pub reward: RewardSchedule,
+    #[serde(default)]
+    pub consensus: ConsensusSpec,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Allocation {
+    pub pub_key: String,
+    pub amount: Amount,
+}
+
+/// proof of work parameters (see `Difficulty`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DifficultySpec {
+    /// leading zero bits of the easiest target
+    pub pow_limit_bits: usize,
+    pub retarget_interval: usize,
+    /// wanted time between two blocks (in micro secs)
+    pub block_time: u64,
+}
+
+impl Default for DifficultySpec {
+    fn default() -> Self {
+        return DifficultySpec { pow_limit_bits: 20, retarget_interval: 10, block_time: 1_000_000 };
+    }
+}
+
+impl From<&DifficultySpec> for Difficulty {
+    fn from(spec: &DifficultySpec) -> Self {
+        return Difficulty {
+            pow_limit: Hash::max_with_leading_zeros(spec.pow_limit_bits),
+            retarget_interval: spec.retarget_interval,
+            block_time: spec.block_time as u128,
+        };
+    }
+}
+
+/// consensus engine of the chain (and who may seal blocks)
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "engine", rename_all = "lowercase")]
+pub enum ConsensusSpec {
+    /// proof of work
+    #[default]
+    Pow,
+    /// proof of authority by pub keys in the order of their turns
+    Poa { authorities: Vec<String> },
+    /// proof of stake by the stakes of the validators before anyone locked balance by a stake tx
+    Pos { validators: Vec<Allocation> },
+}
+
+impl ChainSpec {
+    /// reads a json file if it ends with `.json` and a toml file otherwise
+    pub fn load(path: &Path) -> io::Result<ChainSpec> {
+        let text = fs::read_to_string(path)?;
+        let spec = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str::<ChainSpec>(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        } else {
+            toml::from_str::<ChainSpec>(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        };
+
+        spec.check().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        return Ok(spec);
+    }
+
+    /// checks what the types do not (a chain of such a spec could never grow)
+    pub fn check(&self) -> Result<(), &'static str> {
+        // the timespan of an interval is measured from its first to its last block
+        if self.difficulty.retarget_interval < 2 {
+            return Err("retarget interval has to be at least two rounds");
+        }
+
+        if self.difficulty.pow_limit_bits > 256 {
+            return Err("pow limit has more bits than a hash");
+        }
+
+        if self.max_pkg_size < MIN_PKG_SIZE {
+            return Err("max package size is too small for a full block");
+        }
+
+        // the length of a frame is a u32
+        if self.max_pkg_size > u32::MAX as usize {
+            return Err("max package size does not fit into a frame");
+        }
+
+        let allocations = self.allocations.iter().try_fold(Amount::ZERO, |sum, allocation| sum.checked_add(allocation.amount));
+        if allocations.is_none() {
+            return Err("allocations overflow");
+        }
+
+        match &self.consensus {
+            ConsensusSpec::Pow => {}
+            ConsensusSpec::Poa { authorities } => {
+                if authorities.is_empty() {
+                    return Err("proof of authority needs authorities");
+                }
+            }
+            ConsensusSpec::Pos { validators } => {
+                if validators.iter().all(|validator| validator.amount.is_zero()) {
+                    return Err("proof of stake needs validators with stake");
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// hash of the whole spec (the genesis block commits to it)
+    pub fn gen_id(&self) -> Hash {
+        return Hash::digest(&serde_json::to_vec(self).expect("spec has to be serializable"));
+    }
+
+    pub fn create_consensus(&self) -> Arc<dyn Consensus> {
+        return match &self.consensus {
+            ConsensusSpec::Pow => Arc::new(ProofOfWork { difficulty: Difficulty::from(&self.difficulty) }),
+            ConsensusSpec::Poa { authorities } => Arc::new(ProofOfAuthority::new(authorities.clone())),
+            ConsensusSpec::Pos { validators } => {
+                Arc::new(ProofOfStake::new(validators.iter().map(|validator| (validator.pub_key.clone(), validator.amount)).collect(), self.gen_id()))
+            }
+        };
+    }
+
+    /// the same block on every node of the chain (the root of every branch)
+    pub fn gen_genesis(&self, consensus: &dyn Consensus) -> Block {
+        let target = consensus.get_target(None, &|_| -> BlockHeader { unreachable!("the genesis block has no parent") });
+        return Block::new_genesis(&self.gen_id(), self.genesis_time as u128, target);
+    }
+
+    pub fn get_balances(&self) -> Vec<(String, Amount)> {
+        return self.allocations.iter().map(|allocation| (allocation.pub_key.clone(), allocation.amount)).collect();
+    }
+}
+
+fn default_max_pkg_size() -> usize {
+    return DEFAULT_MAX_PKG_SIZE;
+}
+
+impl Default for ChainSpec {
+    /// proof of work chain without allocations
+    fn default() -> Self {
+        return ChainSpec {
+            name: "greychain".to_string(),
+            magic: DEFAULT_MAGIC,
+            genesis_time: 0,
+            max_pkg_size: DEFAULT_MAX_PKG_SIZE,
+            allocations: Vec::new(),
+            difficulty: DifficultySpec::default(),
+            reward: RewardSchedule::default(),
+            consensus: ConsensusSpec::default(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::blockchain::{Amount, RewardSchedule};
+
+    use super::{ChainSpec, ConsensusSpec, DifficultySpec};
+
+    #[test]
+    fn parse_toml() {
+        let text = r#"
+            name = "testnet"
+            magic = 0x54534554
+            genesis_time = 1700000000000000
+
+            [[allocations]]
+            pub_key = "alice"
+            amount = "12.5 GRY"
+
+            [difficulty]
+            pow_limit_bits = 8
+
+            [consensus]
+            engine = "poa"
+            authorities = ["alice", "bob"]
+        "#;
+
+        let spec = toml::from_str::<ChainSpec>(text).unwrap();
+        assert_eq!(spec.check(), Ok(()));
+        assert_eq!(spec.magic, u32::from_le_bytes(*b"TEST"));
+        assert_eq!(spec.get_balances(), vec![("alice".to_string(), Amount::from_units(1_250_000_000))]);
+        assert_eq!(spec.difficulty.retarget_interval, 10);
+        assert!(matches!(&spec.consensus, ConsensusSpec::Poa { authorities } if authorities.len() == 2));
+
+        // json works the same way and every field counts for the id
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(serde_json::from_str::<ChainSpec>(&json).unwrap().gen_id(), spec.gen_id());
+        assert_ne!(ChainSpec { genesis_time: 0, ..spec.clone() }.gen_id(), spec.gen_id());
+
+        let consensus = spec.create_consensus();
+        assert_eq!(spec.gen_genesis(consensus.as_ref()).hash, spec.gen_genesis(consensus.as_ref()).hash);
+
+        assert!(toml::from_str::<ChainSpec>(&text.replace("pow_limit_bits", "pow_limit")).is_err());
+        assert!(ChainSpec { consensus: ConsensusSpec::Poa { authorities: Vec::new() }, ..spec }.check().is_err());
+
+        // one block has no timespan to retarget by
+        let difficulty = DifficultySpec { retarget_interval: 1, ..DifficultySpec::default() };
+        assert!(ChainSpec { difficulty, ..ChainSpec::default() }.check().is_err());
+        let difficulty = DifficultySpec { retarget_interval: 2, ..DifficultySpec::default() };
+        assert_eq!(ChainSpec { difficulty, ..ChainSpec::default() }.check(), Ok(()));
+
+        // full blocks have to fit into a package
+        assert!(ChainSpec { max_pkg_size: 1000, ..ChainSpec::default() }.check().is_err());
+        assert_eq!(spec.max_pkg_size, 1 << 20);
+
+        // a subsidy without halvings
+        let reward = RewardSchedule { halving_interval: 0, ..RewardSchedule::default() };
+        assert_eq!(ChainSpec { reward, ..ChainSpec::default() }.check(), Ok(()));
+        assert_eq!(reward.get_reward(1000), reward.subsidy);
+
+        // the example of the repo
+        assert_eq!(ChainSpec::load(Path::new("chainspec.toml")).unwrap().magic, u32::from_le_bytes(*b"TEST"));
+    }
+}
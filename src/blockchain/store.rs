@@ -0,0 +1,169 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    collections::HashMap,
+    path::Path
+};
+
+use crate::{net::serialize::{Serializer, to_bytes}, crypto::{Hash, HASH_BYTES}};
+
+use super::Block;
+
+/// size of a record header (length and checksum of the block bytes)
+const RECORD_HEADER_SIZE: usize = 4 + HASH_BYTES;
+
+/// append-only file of blocks (record: len as u32 LE | sha256 of the block bytes | block bytes)
+pub struct BlockStore {
+    file: File,
+    by_hash: HashMap<Hash, u64>,
+    /// offsets of all stored blocks of a height (forks included)
+    by_height: Vec<Vec<u64>>,
+}
+
+impl BlockStore {
+    /// opens (or creates) the store and cuts off an incomplete last record (crash while writing)
+    pub fn open(path: &Path) -> io::Result<BlockStore> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut bytes = Vec::<u8>::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut store = BlockStore { file, by_hash: HashMap::new(), by_height: Vec::new() };
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            match Self::read_record(&bytes[offset..]) {
+                Some((size, block)) => {
+                    store.index(&block, offset as u64);
+                    offset += size;
+                }
+                None => {
+                    eprintln!("ERROR: dropped incomplete block record at offset {} of {}", offset, path.display());
+                    store.file.set_len(offset as u64)?;
+                    break;
+                }
+            }
+        }
+
+        return Ok(store);
+    }
+
+    /// writes the block to disk (before returning)
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let content = to_bytes(block);
+
+        let mut record = Vec::<u8>::with_capacity(RECORD_HEADER_SIZE + content.len());
+        record.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        record.extend_from_slice(&Hash::digest(&content).0);
+        record.extend_from_slice(&content);
+
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        self.index(block, offset);
+        return Ok(());
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        return self.by_hash.contains_key(hash);
+    }
+
+    /// number of heights with at least one block
+    pub fn get_heights(&self) -> usize {
+        return self.by_height.len();
+    }
+
+    pub fn get_at(&mut self, height: usize) -> io::Result<Vec<Block>> {
+        let offsets = self.by_height.get(height).cloned().unwrap_or_default();
+        return offsets.into_iter().map(|offset| self.read_at(offset)).collect();
+    }
+
+    fn read_at(&mut self, offset: u64) -> io::Result<Block> {
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut header)?;
+
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let mut record = header.to_vec();
+        record.resize(RECORD_HEADER_SIZE + len, 0);
+        self.file.read_exact(&mut record[RECORD_HEADER_SIZE..])?;
+
+        return Self::read_record(&record)
+            .map(|(_, block)| block)
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, "corrupted block record"));
+    }
+
+    fn read_record(bytes: &[u8]) -> Option<(usize, Block)> {
+        if bytes.len() < RECORD_HEADER_SIZE {
+            return None;
+        }
+
+        let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let content = bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE+len)?;
+        if Hash::digest(content).0 != bytes[4..RECORD_HEADER_SIZE] {
+            return None;
+        }
+
+        return Some((RECORD_HEADER_SIZE + len, Block::deserialize(content).ok()?.1));
+    }
+
+    fn index(&mut self, block: &Block, offset: u64) {
+        if self.by_height.len() <= block.round {
+            self.by_height.resize(block.round + 1, Vec::new());
+        }
+
+        self.by_height[block.round].push(offset);
+        self.by_hash.insert(block.hash, offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+
+    use crate::{blockchain::{Transaction, Block, Amount}, crypto::Hash, test_utils::create_signer};
+
+    use super::BlockStore;
+
+    #[test]
+    fn reopen_and_recover() {
+        let (miner, sign_key) = create_signer();
+
+        let path = std::env::temp_dir().join(format!("greychain-store-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut blocks = Vec::<Block>::new();
+        let mut prev_hash = Hash::ZERO;
+        for round in 0..3 {
+            let coinbase = Transaction::new_coinbase(&Hash::ZERO, &miner, round, Amount::from_gry(50), &sign_key);
+            let block = Block::new(Vec::new(), Vec::new(), coinbase, prev_hash, round, 0, Hash::ZERO, Vec::new());
+            prev_hash = block.hash;
+            blocks.push(block);
+        }
+
+        let mut store = BlockStore::open(&path).unwrap();
+        for block in &blocks {
+            store.append(block).unwrap();
+        }
+
+        let mut store = BlockStore::open(&path).unwrap();
+        assert_eq!(store.get_heights(), 3);
+        assert!(blocks.iter().all(|b| store.contains(&b.hash)));
+        assert!(store.get_at(1).unwrap() == vec![blocks[1].clone()]);
+
+        // crash while writing the last record
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 10).unwrap();
+
+        let mut store = BlockStore::open(&path).unwrap();
+        assert_eq!(store.get_heights(), 2);
+        assert!(!store.contains(&blocks[2].hash));
+
+        store.append(&blocks[2]).unwrap();
+        let mut store = BlockStore::open(&path).unwrap();
+        assert_eq!(store.get_heights(), 3);
+        assert!(store.get_at(2).unwrap() == vec![blocks[2].clone()]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
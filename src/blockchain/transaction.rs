@@ -1,82 +1,169 @@
-use std::{fmt::Display, sync::atomic::AtomicU64, hash::{Hash, Hasher}, collections::hash_map::DefaultHasher};
+use std::fmt::Display;
 
-use crate::net::serialize::Serializer;
+use rsa::{pss::{Signature, BlindedSigningKey}, sha2::Sha256, signature::RandomizedSigner};
 
-#[derive(Clone)]
+use crate::{net::serialize::Serializer, crypto::{Hash, verify_sign, RSA_BYTES}};
+
+use super::Amount;
+
+/// payee of the txs which lock their amount as stake of the payer (for proof of stake)
+pub const STAKE_PAYEE: &str = "stake";
+
+#[derive(Clone, Serializer)]
 pub struct Transaction {
-    pub id: u64,
-    amount: f64,
+    /// has to be higher than the seq of every earlier tx of the payer (replay protection)
+    pub seq: u64,
+    pub amount: Amount,
+    /// paid by the payer to the miner of the block
+    pub fee: Amount,
     pub payer: String,
-    payee: String,
-}
-
-fn get_next_id() -> u64 {
-    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
-    return NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Release);
+    pub payee: String,
+    sign: Signature,
 }
 
 impl Transaction {
-    pub fn new(payer: &String, payee: &String, amount: f64) -> Transaction {
-        let id = get_next_id();
+    /// `chain_id` is signed as well, so the tx is not valid on other chains (see `ChainSpec::gen_id`)
+    pub fn new(chain_id: &Hash, payer: &String, payee: &String, seq: u64, amount: Amount, fee: Amount, sign_key: &BlindedSigningKey<Sha256>) -> Transaction {
+        let bytes = Self::gen_signed_bytes(chain_id, Self::gen_bytes(seq, amount, fee, payer, payee));
+        let sign = sign_key.sign_with_rng(&mut rand::thread_rng(), &bytes);
 
-        return Transaction { id, payer: payer.to_owned(), payee: payee.to_owned(), amount };
+        return Transaction { seq, payer: payer.to_owned(), payee: payee.to_owned(), amount, fee, sign };
     }
 
-    pub fn gen_nonce(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        return hasher.finish();
+    /// pays the block reward and the fees of the block to the miner (signed by the miner, has no payer)
+    pub fn new_coinbase(chain_id: &Hash, miner: &String, round: usize, reward: Amount, sign_key: &BlindedSigningKey<Sha256>) -> Transaction {
+        let seq = round as u64;
+        let payer = String::new();
+        let bytes = Self::gen_signed_bytes(chain_id, Self::gen_bytes(seq, reward, Amount::ZERO, &payer, miner));
+        let sign = sign_key.sign_with_rng(&mut rand::thread_rng(), &bytes);
+
+        return Transaction { seq, payer, payee: miner.to_owned(), amount: reward, fee: Amount::ZERO, sign };
+    }
+
+    /// coinbase of the genesis block (pays nothing, its signature is the chain id so the block commits to the spec)
+    pub fn new_genesis(chain_id: &Hash) -> Transaction {
+        let sign = Signature::try_from(chain_id.0.as_slice()).expect("any bytes are a signature");
+        return Transaction { seq: 0, payer: String::new(), payee: String::new(), amount: Amount::ZERO, fee: Amount::ZERO, sign };
+    }
+
+    /// locks the amount as stake of the payer (it can not be spent anymore)
+    pub fn new_stake(chain_id: &Hash, payer: &String, seq: u64, amount: Amount, fee: Amount, sign_key: &BlindedSigningKey<Sha256>) -> Transaction {
+        return Self::new(chain_id, payer, &STAKE_PAYEE.to_string(), seq, amount, fee, sign_key);
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        return self.payer.is_empty();
+    }
+
+    pub fn is_stake(&self) -> bool {
+        return self.payee == STAKE_PAYEE;
+    }
+
+    /// amount plus fee (None on overflow)
+    pub fn get_cost(&self) -> Option<Amount> {
+        return self.amount.checked_add(self.fee);
+    }
+
+    /// size of the serialized tx (fees are paid per byte)
+    pub fn get_size(&self) -> usize {
+        return Self::gen_size(&self.payer, &self.payee);
+    }
+
+    /// fee in units per 1000 bytes
+    pub fn get_fee_rate(&self) -> u64 {
+        return Self::gen_fee_rate(self.fee, self.get_size());
+    }
+
+    pub fn gen_size(payer: &str, payee: &str) -> usize {
+        return size_of::<u64>() + 2 * size_of::<Amount>() +
+               size_of::<u32>() + payer.len() +
+               size_of::<u32>() + payee.len() +
+               size_of::<u32>() + RSA_BYTES;
+    }
+
+    pub fn gen_fee_rate(fee: Amount, size: usize) -> u64 {
+        return (fee.units() as u128 * 1000 / size.max(1) as u128).min(u64::MAX as u128) as u64;
+    }
+
+    /// hash of the signed transaction (the id of the tx)
+    pub fn gen_hash(&self) -> Hash {
+        let mut bytes = self.to_bytes();
+        bytes.extend_from_slice(&Box::<[u8]>::from(self.sign.clone()));
+        return Hash::digest(&bytes);
+    }
+
+    /// canonical encoding (little endian, length prefixed strings)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        return Self::gen_bytes(self.seq, self.amount, self.fee, &self.payer, &self.payee);
+    }
+
+    /// checks if the transaction was signed by the payer (or by the miner for coinbase txs) for the chain `chain_id`
+    pub fn verify(&self, chain_id: &Hash) -> bool {
+        let signer = if self.is_coinbase() { &self.payee } else { &self.payer };
+        return verify_sign(signer, &Self::gen_signed_bytes(chain_id, self.to_bytes()), &self.sign);
+    }
+
+    /// what the payer signs: the chain id and the canonical encoding (so the tx can not be replayed on other chains)
+    fn gen_signed_bytes(chain_id: &Hash, bytes: Vec<u8>) -> Vec<u8> {
+        let mut signed = chain_id.0.to_vec();
+        signed.extend_from_slice(&bytes);
+        return signed;
+    }
+
+    fn gen_bytes(seq: u64, amount: Amount, fee: Amount, payer: &String, payee: &String) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+
+        bytes.extend_from_slice(&seq.to_le_bytes());
+        bytes.extend_from_slice(&amount.units().to_le_bytes());
+        bytes.extend_from_slice(&fee.units().to_le_bytes());
+        bytes.extend_from_slice(&(payer.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(payer.as_bytes());
+        bytes.extend_from_slice(&(payee.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(payee.as_bytes());
+
+        return bytes;
     }
 }
 
 impl Display for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "id: {}\namount: {} GRY\npayer:\n{}payee:\n{}", self.id, self.amount, self.payer, &self.payee);
+        return write!(f, "id: {}\nseq: {}\namount: {}\nfee: {}\npayer:\n{}payee:\n{}", self.gen_hash(), self.seq, self.amount, self.fee, self.payer, &self.payee);
     }
 }
 
 impl PartialEq for Transaction {
     fn eq(&self, other: &Self) -> bool {
-        return self.id == other.id;
+        return self.gen_hash() == other.gen_hash();
     }
 }
 
-impl Hash for Transaction {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
-        self.amount.to_be_bytes().hash(state);
-        self.payer.hash(state);
-        self.payee.hash(state);
-    }
-}
+#[cfg(test)]
+mod tests {
+    use crate::{crypto::Hash, test_utils::create_signer};
 
-impl Serializer for Transaction {
-    fn serialize(&self, dst: &mut [u8]) -> usize {
-        let mut start: usize = 0;
+    use crate::blockchain::Amount;
 
-        start += self.id.serialize(&mut dst[start..]);
-        start += self.amount.serialize(&mut dst[start..]);
-        start += self.payer.serialize(&mut dst[start..]); 
-        start += self.payee.serialize(&mut dst[start..]);
+    use super::Transaction;
 
-        return start;
-    }
+    #[test]
+    fn verify_signed_by_payer() {
+        let (payer, sign_key) = create_signer();
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        let mut start: usize = 0;
+        let chain_id = Hash::digest(b"chain");
 
-        let (size, id) = u64::deserialize(&bytes[start..]);
-        start += size;
+        let tx = Transaction::new(&chain_id, &payer, &"payee".to_string(), 0, Amount::from_units(420_000_000), Amount::ZERO, &sign_key);
+        assert!(tx.verify(&chain_id));
 
-        let (size, amount) = f64::deserialize(&bytes[start..]);
-        start += size;
-
-        let (size, payer) = String::deserialize(&bytes[start..]);
-        start += size;
+        // it can not be replayed on another chain
+        assert!(!tx.verify(&Hash::digest(b"other chain")));
+    }
 
-        let (size, payee) = String::deserialize(&bytes[start..]);
-        start += size;
+    #[test]
+    fn verify_foreign_payer_fails() {
+        let (payer, _) = create_signer();
+        let (_, sign_key) = create_signer();
 
-        return (start, Transaction { id, amount, payer, payee });
+        let tx = Transaction::new(&Hash::ZERO, &payer, &"payee".to_string(), 0, Amount::from_units(420_000_000), Amount::ZERO, &sign_key);
+        assert!(!tx.verify(&Hash::ZERO));
     }
 }
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use crate::crypto::Hash;
+
+use super::{Block, BlockHeader, Consensus};
+
+/// max blocks (or headers) kept while their parent is unknown
+const MAX_ORPHANS: usize = 64;
+
+/// what the tree needs to link blocks or headers
+pub trait TreeItem {
+    fn get_hash(&self) -> Hash;
+    fn get_prev_hash(&self) -> Hash;
+    fn get_round(&self) -> usize;
+}
+
+impl TreeItem for Block {
+    fn get_hash(&self) -> Hash {
+        return self.hash;
+    }
+
+    fn get_prev_hash(&self) -> Hash {
+        return self.prev_hash;
+    }
+
+    fn get_round(&self) -> usize {
+        return self.round;
+    }
+}
+
+impl TreeItem for BlockHeader {
+    fn get_hash(&self) -> Hash {
+        return self.hash;
+    }
+
+    fn get_prev_hash(&self) -> Hash {
+        return self.prev_hash;
+    }
+
+    fn get_round(&self) -> usize {
+        return self.round;
+    }
+}
+
+struct Node<T> {
+    item: T,
+    /// cumulative weight of the branch up to (and including) this item
+    weight: u128,
+}
+
+/// tree of all known blocks (or headers) with the branch the fork choice of the consensus picked
+/// and the orphans waiting for their parent (shared by `Blockchain` and `HeaderChain`)
+pub struct ChainTree<T> {
+    nodes: HashMap<Hash, Node<T>>,
+    /// hashes of the branch with the most weight (genesis first)
+    chain: Vec<Hash>,
+    orphans: Vec<T>,
+}
+
+impl<T: TreeItem> ChainTree<T> {
+    pub fn new(genesis: T) -> ChainTree<T> {
+        let chain = vec![genesis.get_hash()];
+        let nodes = HashMap::from([(genesis.get_hash(), Node { item: genesis, weight: 0 })]);
+        return ChainTree { nodes, chain, orphans: Vec::new() };
+    }
+
+    /// true for every item in the tree (main chain and forks, no orphans)
+    pub fn contains(&self, hash: &Hash) -> bool {
+        return self.nodes.contains_key(hash);
+    }
+
+    pub fn is_orphan(&self, hash: &Hash) -> bool {
+        return self.orphans.iter().any(|orphan| orphan.get_hash() == *hash);
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&T> {
+        return self.nodes.get(hash).map(|node| &node.item);
+    }
+
+    #[cfg(test)]
+    pub fn get_mut(&mut self, hash: &Hash) -> Option<&mut T> {
+        return self.nodes.get_mut(hash).map(|node| &mut node.item);
+    }
+
+    pub fn is_in_chain(&self, hash: &Hash) -> bool {
+        return match self.nodes.get(hash) {
+            Some(node) => self.chain.get(node.item.get_round()) == Some(hash),
+            None => false
+        };
+    }
+
+    /// adds an item with a known parent, `weight` is the one of the item alone
+    pub fn insert(&mut self, item: T, weight: u128) {
+        let parent_weight = self.nodes.get(&item.get_prev_hash()).map_or(0, |parent| parent.weight);
+        self.nodes.insert(item.get_hash(), Node { item, weight: parent_weight.saturating_add(weight) });
+    }
+
+    /// keeps an item until its parent arrives (the oldest orphan goes if there are too many)
+    pub fn add_orphan(&mut self, item: T) {
+        if self.is_orphan(&item.get_hash()) {
+            return;
+        }
+
+        if self.orphans.len() >= MAX_ORPHANS {
+            self.orphans.remove(0);
+        }
+        self.orphans.push(item);
+    }
+
+    /// removes and returns the orphans whose parent is `hash`
+    pub fn take_children(&mut self, hash: &Hash) -> Vec<T> {
+        let (children, orphans) = std::mem::take(&mut self.orphans).into_iter()
+            .partition(|orphan| orphan.get_prev_hash() == *hash);
+        self.orphans = orphans;
+        return children;
+    }
+
+    /// fork choice of the consensus engine (the same on every node)
+    pub fn is_better(&self, hash: &Hash, consensus: &dyn Consensus) -> bool {
+        let Some(tip) = self.chain.last() else {
+            return true;
+        };
+
+        return consensus.is_better(self.nodes[hash].weight, hash, self.nodes[tip].weight, tip);
+    }
+
+    /// branch of `tip` which is not part of the main chain (oldest first) and the round after the fork point
+    pub fn get_branch(&self, tip: Hash) -> (Vec<Hash>, usize) {
+        let mut branch = Vec::<Hash>::new();
+        let mut hash = tip;
+        // every branch starts at the genesis block
+        while !self.is_in_chain(&hash) {
+            branch.push(hash);
+            hash = self.nodes[&hash].item.get_prev_hash();
+        }
+        branch.reverse();
+
+        return (branch, self.nodes[&hash].item.get_round() + 1);
+    }
+
+    /// replaces the main chain after `fork_round` by `branch` (see `get_branch`), returns the dropped hashes
+    pub fn set_chain(&mut self, branch: Vec<Hash>, fork_round: usize) -> Vec<Hash> {
+        let dropped = self.chain.split_off(fork_round);
+        self.chain.extend(branch);
+        return dropped;
+    }
+
+    /// removes the item and all its descendants
+    pub fn remove_branch(&mut self, hash: Hash) {
+        let mut stale = vec![hash];
+        while let Some(hash) = stale.pop() {
+            self.nodes.remove(&hash);
+            stale.extend(self.nodes.values().filter(|node| node.item.get_prev_hash() == hash).map(|node| node.item.get_hash()));
+        }
+    }
+
+    /// items of the main chain (genesis first)
+    pub fn chain(&self) -> impl DoubleEndedIterator<Item = &T> {
+        return self.chain.iter().map(|hash| &self.nodes[hash].item);
+    }
+
+    /// hashes of the main chain (genesis first)
+    pub fn get_chain(&self) -> &[Hash] {
+        return &self.chain;
+    }
+
+    /// every item in the tree
+    pub fn items(&self) -> impl Iterator<Item = &T> {
+        return self.nodes.values().map(|node| &node.item);
+    }
+
+    pub fn get_round(&self) -> usize {
+        return self.chain.len();
+    }
+
+    pub fn get_cur_hash(&self) -> Hash {
+        return *self.chain.last().unwrap_or(&Hash::ZERO);
+    }
+}
@@ -1,8 +1,65 @@
-use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::fmt::{Display, Debug};
+
+use rsa::{
+    RsaPrivateKey, RsaPublicKey,
+    sha2::{Sha256, Digest},
+    pss::{Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    signature::Verifier
+};
+
+use crate::net::serialize::Serializer;
 
 pub const RSA_BITS: usize = 2048;
-pub const RSA_PEM_SIZE: usize = 52 + RSA_BITS/4/64 + RSA_BITS/4;
 pub const RSA_BYTES: usize = RSA_BITS/8;
+pub const HASH_BYTES: usize = 32;
+
+/// SHA-256 digest (compared as big-endian 256-bit number)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serializer)]
+pub struct Hash(pub [u8; HASH_BYTES]);
+
+impl Hash {
+    pub const ZERO: Hash = Hash([0u8; HASH_BYTES]);
+
+    pub fn digest(bytes: &[u8]) -> Hash {
+        return Hash(Sha256::digest(bytes).into());
+    }
+
+    /// biggest hash with the first `bits` bits set to zero
+    pub const fn max_with_leading_zeros(bits: usize) -> Hash {
+        let mut hash = [0xffu8; HASH_BYTES];
+
+        let mut i = 0;
+        while i < HASH_BYTES {
+            if (i+1)*8 <= bits {
+                hash[i] = 0x0;
+            } else if i*8 < bits {
+                hash[i] = 0xff >> (bits - i*8);
+            }
+            i += 1;
+        }
+
+        return Hash(hash);
+    }
+
+    /// expected number of hashes needed to find one below this target
+    pub fn work(&self) -> u128 {
+        let top = u128::from_be_bytes(self.0[..16].try_into().unwrap());
+        return (!top / top.saturating_add(1)).saturating_add(1);
+    }
+}
+
+impl Display for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.0.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    }
+}
+
+impl Debug for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return Display::fmt(self, f);
+    }
+}
 
 pub fn create_key_pair() -> (RsaPublicKey, RsaPrivateKey) {
     let mut rng = rand::thread_rng();
@@ -12,6 +69,14 @@ pub fn create_key_pair() -> (RsaPublicKey, RsaPrivateKey) {
     return (pub_key, priv_key);
 }
 
+pub fn verify_sign(pub_key_pem: &str, msg: &[u8], sign: &Signature) -> bool {
+    if let Ok(pub_key) = RsaPublicKey::from_public_key_pem(pub_key_pem) {
+        return VerifyingKey::<Sha256>::from(pub_key).verify(msg, sign).is_ok();
+    }
+
+    return false;
+}
+
 #[cfg(test)]
 mod tests {
     use rsa::{
@@ -21,7 +86,20 @@ mod tests {
         signature::{Keypair, RandomizedSigner, Verifier}
     };
 
-    use crate::crypto::RSA_BITS;
+    use crate::crypto::{RSA_BITS, Hash};
+
+    #[test]
+    pub fn hash_leading_zeros() {
+        assert_eq!(Hash::max_with_leading_zeros(0), Hash([0xff; 32]));
+        assert_eq!(Hash::max_with_leading_zeros(256), Hash::ZERO);
+
+        let target = Hash::max_with_leading_zeros(20);
+        assert_eq!(target.0[..4], [0x00, 0x00, 0x0f, 0xff]);
+        assert!(Hash::digest(b"test message") > target);
+
+        assert_eq!(target.work(), 1 << 20);
+        assert_eq!(Hash::max_with_leading_zeros(0).work(), 1);
+    }
 
     #[test]
     pub fn sign() {
@@ -69,8 +147,7 @@ mod tests {
 
         let wrong_msg = "wrong test message";
         if let Ok(()) = veri_key.verify(wrong_msg.as_bytes(), &sign) {
-            println!("verify should return error");
-            assert!(false);
+            panic!("verify should return error");
         }
     }
 }
@@ -1,54 +1,129 @@
+#![allow(clippy::needless_return, clippy::module_inception, clippy::items_after_test_module)]
+
 mod wallet;
 mod net;
 mod blockchain;
 mod crypto;
+#[cfg(test)]
+mod test_utils;
+
+use std::{time::Duration, thread::sleep, net::{IpAddr, Ipv4Addr, SocketAddr}, path::Path};
 
-use std::{time::Duration, thread::sleep};
+use wallet::{Wallet, WalletConfig};
 
-use wallet::Wallet;
+use rsa::{RsaPrivateKey, RsaPublicKey, pkcs8::EncodePublicKey};
 
-use crate::net::{tcp::get_pkgs_send, node::Node};
+use crate::{net::{tcp::get_pkgs_send, node::Node}, crypto::{Hash, create_key_pair}, blockchain::{Amount, ChainError, ChainSpec, ConsensusSpec, Allocation, STAKE_PAYEE}};
 
 extern crate rsa;
 extern crate rand;
 extern crate digest;
 
+
 fn main() {
     const WALLETS_COUNT: usize = 7;
     const TXS_PER_WALLET: usize = 3;
 
-    let wallets = create_test_wallets(WALLETS_COUNT);
+    // `greychain pow` mines for real, `greychain pos` picks the proposers by stake,
+    // `greychain <spec file>` runs the chain of the spec (the wallets get new keys, so it has to be proof of work),
+    // otherwise the wallets take turns signing blocks
+    let mode = std::env::args().nth(1).unwrap_or_default();
+    let data_dir = Path::new(DATA_DIR);
+    let mut wallets = match mode.as_str() {
+        "" => create_test_wallets(WALLETS_COUNT, data_dir),
+        "pow" => create_wallets(WALLETS_COUNT, &ChainSpec::default(), data_dir),
+        "pos" => create_validator_wallets(WALLETS_COUNT, data_dir, |validators| {
+            let validators = validators.iter().map(|validator| Allocation { pub_key: validator.clone(), amount: Amount::from_gry(1) }).collect();
+            ConsensusSpec::Pos { validators }
+        }),
+        path => match ChainSpec::load(Path::new(path)) {
+            Ok(spec) => create_wallets(WALLETS_COUNT, &spec, data_dir),
+            Err(err) => {
+                eprintln!("ERROR: could not load chain spec {}: {}", path, err);
+                return;
+            }
+        },
+    };
+
+    fund_wallets(&wallets);
+    if mode == "pos" {
+        stake_validators(&wallets);
+    }
 
     create_txs(&wallets, TXS_PER_WALLET);
 
     wait_for_wallets(&wallets);
 
+    // joins late and only follows the headers and its own txs (it can not check the proposers of proof of stake)
+    if mode != "pos" {
+        let light_wallet = Wallet::new_light(&local_config(data_dir), &get_master_nodes(&wallets), wallets[0].get_spec()).expect("ERROR: could not create wallet");
+        wallets[0].send_tx(&light_wallet.pub_key_pem, Amount::from_gry(1), wallets[0].estimate_fee(&light_wallet.pub_key_pem));
+        wallets.push(light_wallet);
+
+        wait_for_wallets(&wallets);
+    }
+
     wallets[0].show_network();
 
     let txs = wallets[0].get_tx_ids();
-    let hashes = wallets.iter().map(|w| w.get_cur_hash()).collect::<Vec<u64>>();
+    let hashes = wallets.iter().map(|w| w.get_cur_hash()).collect::<Vec<Hash>>();
     let blockchain_hashes = wallets[0].get_blockchain_hashes();
     let net_lens = wallets.iter().map(|w| w.get_network_len()).collect::<Vec<usize>>();
+    let balances = wallets.iter().map(|w| w.get_balance()).collect::<Vec<Amount>>();
+    let mining_stats = wallets.iter().map(|w| w.get_mining_stats().to_string()).collect::<Vec<String>>();
+    let chain_errors = wallets.iter().filter_map(|w| w.verify_chain().err()).collect::<Vec<ChainError>>();
+    let proven_txs = txs.iter().filter(|id| {
+        match (wallets[0].get_tx(**id), wallets[0].get_merkle_proof(**id)) {
+            (Some(tx), Some((block_hash, proof))) => wallets.iter().all(|w| w.verify_merkle_proof(&block_hash, &tx, &proof)),
+            _ => false
+        }
+    }).count();
 
     shutdown_test_wallets(wallets);
 
     println!("\n-------------------"); 
     println!("pkgs total send: {}", get_pkgs_send()); 
     println!("net_lens: {:?}", net_lens);
+    println!("txs with valid merkle proofs: {}/{}", proven_txs, txs.len());
     println!("hashes:\n{}", hashes.iter().map(|h| {h.to_string() + "\n"}).collect::<String>());
     println!("blockchain hashes:\n{}", blockchain_hashes.iter()
              .map(|(prev, cur)| prev.to_string() + "\n" + &cur.to_string() + "\n")
              .collect::<String>());
     println!("txs: {:?}", txs); 
     println!("txs count: {:?}", txs.len()); 
+    println!("balances: {:?}", balances);
+    println!("mining stats: {:?}", mining_stats);
+    println!("invalid chains: {:?}", chain_errors);
     println!("-------------------"); 
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{time::Duration, thread::sleep};
+    use std::{time::Duration, thread::sleep, path::{Path, PathBuf}, fs};
 
-    use crate::{wait_for_wallets, create_test_wallets, shutdown_test_wallets, create_txs};
+    use rsa::{RsaPublicKey, pkcs8::EncodePublicKey};
+
+    use crate::{wait_for_wallets, create_test_wallets, create_wallets, shutdown_test_wallets, create_txs, fund_wallets, get_master_nodes, local_config,
+                wallet::Wallet, blockchain::{Amount, ChainSpec, ConsensusSpec, Allocation}, crypto::create_key_pair};
+
+    /// data dir of a single test (tests run in parallel), removed at the end of the test
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> TestDir {
+            return TestDir(std::env::temp_dir().join(format!("greychain-test-{:016x}", rand::random::<u64>())));
+        }
+
+        fn path(&self) -> &Path {
+            return &self.0;
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 
     #[test]
     fn network_3wallets() {
@@ -70,11 +145,31 @@ mod tests {
         complete_network(15);
     }
 
+    #[test]
+    fn other_chain_3wallets() {
+        other_chain_is_refused(3);
+    }
+
     #[test]
     fn blockchain_equal_3wallets_2tx() {
         blockchain_equal(3, 2);
     }
 
+    #[test]
+    fn late_wallet_syncs_3wallets_2tx() {
+        late_wallet_syncs(3, 2);
+    }
+
+    #[test]
+    fn light_wallet_3wallets_2tx() {
+        light_wallet(3, 2);
+    }
+
+    #[test]
+    fn balances_3wallets_2tx() {
+        check_balances(3, 2);
+    }
+
     #[test]
     fn txs_3wallets_2tx() {
         check_txs(3, 2);
@@ -90,10 +185,44 @@ mod tests {
         check_txs(14, 4);
     }
 
+    #[test]
+    fn pow_3wallets_2tx() {
+        pow_chain(3, 2);
+    }
+
+    #[test]
+    fn restart_continues_chain() {
+        let dir = TestDir::new();
+        let key = create_key_pair().1;
+        let authority = RsaPublicKey::from(&key).to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap();
+        let spec = ChainSpec { consensus: ConsensusSpec::Poa { authorities: vec![authority] }, ..ChainSpec::default() };
+
+        let wallet = Wallet::with_key(key.clone(), &local_config(dir.path()), &Vec::new(), &spec).unwrap();
+        for _ in 0..3 {
+            wallet.mine_reward();
+            wait_for_wallets(std::slice::from_ref(&wallet));
+        }
+        let hashes = wallet.get_blockchain_hashes();
+        assert_eq!(hashes.len(), 4);
+        wallet.shutdown();
+
+        // another port, but the same key and data dir
+        let wallet = Wallet::with_key(key, &local_config(dir.path()), &Vec::new(), &spec).unwrap();
+        assert_eq!(wallet.get_blockchain_hashes(), hashes);
+        assert_eq!(wallet.verify_chain(), Ok(()));
+        wallet.shutdown();
+
+        // a new key starts at the genesis block
+        let wallet = Wallet::new_master_node(&local_config(dir.path()), &spec).unwrap();
+        assert_eq!(wallet.get_blockchain_hashes().len(), 1);
+        wallet.shutdown();
+    }
+
 
 
     fn complete_network(wallets_count: usize) {
-        let wallets = create_test_wallets(wallets_count);
+        let dir = TestDir::new();
+        let wallets = create_test_wallets(wallets_count, dir.path());
 
         wait_for_wallets(&wallets);
 
@@ -106,7 +235,8 @@ mod tests {
     }
 
     fn complete_network_wait_secs(wallets_count: usize, secs: u64) {
-        let wallets = create_test_wallets(wallets_count);
+        let dir = TestDir::new();
+        let wallets = create_test_wallets(wallets_count, dir.path());
 
         sleep(Duration::from_secs(secs));
 
@@ -118,8 +248,31 @@ mod tests {
         shutdown_test_wallets(wallets);
     }
 
+    /// a wallet of another chain spec is not registered (and does not learn about the other nodes)
+    fn other_chain_is_refused(wallets_count: usize) {
+        let dir = TestDir::new();
+        let mut wallets = create_test_wallets(wallets_count, dir.path());
+        wait_for_wallets(&wallets);
+
+        let other = ChainSpec { name: "other".to_string(), ..wallets[0].get_spec().clone() };
+        wallets.push(Wallet::new(&local_config(dir.path()), &get_master_nodes(&wallets), &other).unwrap());
+
+        wait_for_wallets(&wallets);
+
+        for wallet in &wallets[..wallets_count] {
+            assert_eq!(wallet.get_network_len(), wallets_count-1);
+        }
+        // the master node disconnects it in the handshake
+        assert_eq!(wallets.last().unwrap().get_network_len(), 0);
+
+        shutdown_test_wallets(wallets);
+    }
+
     fn blockchain_equal(wallets_count: usize, txs_count: usize) {
-        let wallets = create_test_wallets(wallets_count);
+        let dir = TestDir::new();
+        let wallets = create_test_wallets(wallets_count, dir.path());
+
+        fund_wallets(&wallets);
 
         create_txs(&wallets, txs_count);
 
@@ -130,52 +283,265 @@ mod tests {
             assert_eq!(wallet.get_cur_hash(), cur_hash);
         }
 
+        for wallet in &wallets {
+            assert_eq!(wallet.verify_chain(), Ok(()));
+        }
+
         shutdown_test_wallets(wallets);
     }
 
-    fn check_txs(wallets_count: usize, pre_wallet_txs_count: usize) {
-        let wallets = create_test_wallets(wallets_count);
+    /// a wallet joining after some rounds has to download the blocks mined before
+    fn late_wallet_syncs(wallets_count: usize, txs_count: usize) {
+        let dir = TestDir::new();
+        let mut wallets = create_test_wallets(wallets_count, dir.path());
+
+        fund_wallets(&wallets);
+
+        create_txs(&wallets, txs_count);
+
+        wait_for_wallets(&wallets);
+
+        let master_nodes = get_master_nodes(&wallets);
+        wallets.push(Wallet::new(&local_config(dir.path()), &master_nodes, wallets[0].get_spec()).unwrap());
+
+        wait_for_wallets(&wallets);
+
+        let late_wallet = wallets.last().unwrap();
+        assert_eq!(late_wallet.get_cur_hash(), wallets[0].get_cur_hash());
+        assert_eq!(late_wallet.verify_chain(), Ok(()));
+
+        shutdown_test_wallets(wallets);
+    }
+
+    fn light_wallet(wallets_count: usize, txs_count: usize) {
+        let dir = TestDir::new();
+        let mut wallets = create_test_wallets(wallets_count, dir.path());
+
+        fund_wallets(&wallets);
+
+        create_txs(&wallets, txs_count);
 
-        create_txs(&wallets, pre_wallet_txs_count);
+        wait_for_wallets(&wallets);
+
+        let master_nodes = get_master_nodes(&wallets);
+        let light_wallet = Wallet::new_light(&local_config(dir.path()), &master_nodes, wallets[0].get_spec()).unwrap();
+        wallets[0].send_tx(&light_wallet.pub_key_pem, Amount::from_gry(1), wallets[0].estimate_fee(&light_wallet.pub_key_pem));
+        wallets.push(light_wallet);
+
+        wait_for_wallets(&wallets);
+
+        let light_wallet = wallets.last().unwrap();
+        assert_eq!(light_wallet.get_cur_hash(), wallets[0].get_cur_hash());
+        assert_eq!(light_wallet.get_balance(), Amount::from_gry(1));
+        assert_eq!(wallets[0].get_balance_of(&light_wallet.pub_key_pem), Amount::from_gry(1));
+        assert_eq!(light_wallet.get_blockchain_hashes(), wallets[0].get_blockchain_hashes());
+
+        // proofs of the full node are checked against the headers only
+        let tx_ids = light_wallet.get_tx_ids();
+        assert_eq!(tx_ids.len(), 1);
+        let (tx, (block_hash, proof)) = (wallets[0].get_tx(tx_ids[0]).unwrap(), wallets[0].get_merkle_proof(tx_ids[0]).unwrap());
+        assert!(light_wallet.verify_merkle_proof(&block_hash, &tx, &proof));
+
+        let other_id = wallets[0].get_tx_ids().into_iter().find(|id| *id != tx_ids[0]).unwrap();
+        let (other_tx, (other_hash, other_proof)) = (wallets[0].get_tx(other_id).unwrap(), wallets[0].get_merkle_proof(other_id).unwrap());
+        assert!(light_wallet.verify_merkle_proof(&other_hash, &other_tx, &other_proof));
+        assert!(!light_wallet.verify_merkle_proof(&other_hash, &tx, &other_proof));
+
+        // the proposers of proof of stake can not be checked by headers only
+        let validators = vec![Allocation { pub_key: wallets[0].pub_key_pem.clone(), amount: Amount::from_gry(1) }];
+        let pos = ChainSpec { consensus: ConsensusSpec::Pos { validators }, ..ChainSpec::default() };
+        assert!(Wallet::new_light(&local_config(dir.path()), &master_nodes, &pos).is_err());
+
+        shutdown_test_wallets(wallets);
+    }
+
+    fn check_balances(wallets_count: usize, txs_count: usize) {
+        let dir = TestDir::new();
+        let wallets = create_test_wallets(wallets_count, dir.path());
+
+        fund_wallets(&wallets);
+
+        create_txs(&wallets, txs_count);
+
+        wait_for_wallets(&wallets);
+
+        // all coins come from the allocations and the block rewards (no halving in a few rounds)
+        let spec = wallets[0].get_spec();
+        let allocated = spec.get_balances().iter().map(|(_, amount)| amount.units()).sum::<u64>();
+        let blocks_count = wallets[0].get_blockchain_hashes().len() - 1;
+        let reward = spec.reward.get_reward(1);
+        let balances = wallets.iter().map(|w| w.get_balance()).collect::<Vec<Amount>>();
+        let total = balances.iter().try_fold(Amount::ZERO, |total, b| total.checked_add(*b));
+        assert_eq!(total, Some(Amount::from_units(allocated + blocks_count as u64 * reward.units())));
+
+        // every wallet has to agree on every balance
+        for wallet in &wallets {
+            for (other, balance) in wallets.iter().zip(&balances) {
+                assert_eq!(wallet.get_balance_of(&other.pub_key_pem), *balance);
+            }
+        }
+
+        shutdown_test_wallets(wallets);
+    }
+
+    /// the other tests seal blocks by proof of authority, here the wallets mine for their rewards and the txs
+    fn pow_chain(wallets_count: usize, txs_count: usize) {
+        let dir = TestDir::new();
+        let mut spec = ChainSpec::default();
+        spec.difficulty.pow_limit_bits = 8;
+        let wallets = create_wallets(wallets_count, &spec, dir.path());
+
+        fund_wallets(&wallets);
+
+        let mut expected = create_txs(&wallets, txs_count);
+        expected.sort_unstable();
 
         wait_for_wallets(&wallets);
 
-        let txs_total = (pre_wallet_txs_count*wallets_count).try_into().expect("cound not cast u64 into usize");
-        let expected = (0..txs_total).collect::<Vec<u64>>();
+        let cur_hash = wallets[0].get_cur_hash();
         for wallet in &wallets {
+            assert_eq!(wallet.get_cur_hash(), cur_hash);
+            assert_eq!(wallet.verify_chain(), Ok(()));
+
             let mut res = wallet.get_tx_ids();
             res.sort_unstable();
+            assert_eq!(res, expected);
+        }
+
+        // the coins of the funded wallets come from the allocations and the rewards of the mined blocks
+        let allocated = spec.get_balances().iter().map(|(_, amount)| amount.units()).sum::<u64>();
+        let blocks_count = wallets[0].get_blockchain_hashes().len() - 1;
+        let total = wallets.iter().try_fold(Amount::ZERO, |total, w| total.checked_add(w.get_balance()));
+        assert_eq!(total, Some(Amount::from_units(allocated + blocks_count as u64 * spec.reward.get_reward(1).units())));
+
+        shutdown_test_wallets(wallets);
+    }
+
+    fn check_txs(wallets_count: usize, pre_wallet_txs_count: usize) {
+        let dir = TestDir::new();
+        let wallets = create_test_wallets(wallets_count, dir.path());
 
-            // tx ids will not always start from 0 (global id counter)
-            let offset = *res.first().expect("expected at least one tx");
-            res = res.iter_mut().map(|id| *id - offset).collect();
+        fund_wallets(&wallets);
+
+        let mut expected = create_txs(&wallets, pre_wallet_txs_count);
+        expected.sort_unstable();
+
+        wait_for_wallets(&wallets);
+
+        for wallet in &wallets {
+            assert_eq!(wallet.verify_chain(), Ok(()));
+
+            let mut res = wallet.get_tx_ids();
+            res.sort_unstable();
 
             assert_eq!(res, expected);
         }
 
+        // every wallet can check the inclusion proofs of another one
+        for id in expected {
+            let tx = wallets[0].get_tx(id).expect("expected tx in blockchain");
+            let (block_hash, proof) = wallets[0].get_merkle_proof(id).expect("expected merkle proof");
+
+            for wallet in &wallets {
+                assert!(wallet.verify_merkle_proof(&block_hash, &tx, &proof));
+            }
+        }
+
         shutdown_test_wallets(wallets);
     }       
 }
 
-fn create_test_wallets(wallets_count: usize) -> Vec<Wallet> {
+/// the wallets of the examples listen on a free port of the loopback interface
+const LOCALHOST: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+/// where the wallets of the example store their blocks
+const DATA_DIR: &str = "blockchains";
+
+/// genesis balance of each wallet of `create_validator_wallets`
+const TEST_ALLOCATION: Amount = Amount::from_gry(50);
+
+/// test wallets get new keys, so they never continue a stored blockchain
+/// (they are the authorities and take turns sealing blocks, so no time is spent on mining)
+fn create_test_wallets(wallets_count: usize, data_dir: &Path) -> Vec<Wallet> {
+    return create_validator_wallets(wallets_count, data_dir, |authorities| ConsensusSpec::Poa { authorities: authorities.to_vec() });
+}
+
+/// wallets with new keys which are the validators of the consensus (the first one is the master node)
+/// and have an allocation in the genesis block
+fn create_validator_wallets(wallets_count: usize, data_dir: &Path, gen_consensus: impl Fn(&[String]) -> ConsensusSpec) -> Vec<Wallet> {
+    let keys = (0..wallets_count).map(|_| create_key_pair().1).collect::<Vec<RsaPrivateKey>>();
+    let validators = keys.iter()
+        .map(|key| RsaPublicKey::from(key).to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap())
+        .collect::<Vec<String>>();
+
+    let spec = ChainSpec {
+        allocations: validators.iter().map(|validator| Allocation { pub_key: validator.clone(), amount: TEST_ALLOCATION }).collect(),
+        consensus: gen_consensus(&validators),
+        ..ChainSpec::default()
+    };
+
+    let mut wallets = Vec::<Wallet>::with_capacity(wallets_count);
+    for key in keys {
+        let master_nodes = if wallets.is_empty() { Vec::new() } else { get_master_nodes(&wallets) };
+        wallets.push(Wallet::with_key(key, &local_config(data_dir), &master_nodes, &spec).expect("ERROR: could not create wallet"));
+    }
+
+    return wallets;
+}
+
+/// wallets with new keys (the first one is the master node)
+fn create_wallets(wallets_count: usize, spec: &ChainSpec, data_dir: &Path) -> Vec<Wallet> {
     let mut wallets = Vec::<Wallet>::with_capacity(wallets_count);
-    wallets.push(Wallet::new_master_node());
+    wallets.push(Wallet::new_master_node(&local_config(data_dir), spec).expect("ERROR: could not create wallet"));
 
-    let master_nodes = vec![Node{ pub_key: wallets[0].pub_key_pem.clone(), port: wallets[0].port, online: true}];
-    wallets.resize_with(wallets_count, || { Wallet::new(&master_nodes) });
+    let master_nodes = get_master_nodes(&wallets);
+    wallets.resize_with(wallets_count, || { Wallet::new(&local_config(data_dir), &master_nodes, spec).expect("ERROR: could not create wallet") });
 
     return wallets;
 }
 
-fn create_txs(wallets: &Vec<Wallet>, txs_count: usize) {
+fn local_config(data_dir: &Path) -> WalletConfig {
+    return WalletConfig::new(LOCALHOST, data_dir);
+}
+
+fn get_master_nodes(wallets: &[Wallet]) -> Vec<Node> {
+    return vec![Node{ pub_key: wallets[0].pub_key_pem.clone(), addr: wallets[0].addr, online: true}];
+}
+
+/// wallets without an allocation mine until they got a reward (only once every wallet is connected,
+/// so no tx can end up before the reward funding it)
+fn fund_wallets(wallets: &[Wallet]) {
+    wait_for_wallets(wallets);
+
+    while wallets.iter().any(|w| w.get_balance().is_zero()) {
+        for wallet in wallets.iter().filter(|w| w.get_balance().is_zero()) {
+            wallet.mine_reward();
+        }
+        wait_for_wallets(wallets);
+    }
+}
+
+/// every validator locks some of its balance as stake
+fn stake_validators(wallets: &[Wallet]) {
+    for wallet in wallets {
+        wallet.send_stake(Amount::from_gry(10), wallet.estimate_fee(STAKE_PAYEE));
+    }
+    wait_for_wallets(wallets);
+}
+
+fn create_txs(wallets: &[Wallet], txs_count: usize) -> Vec<Hash> {
+    let mut ids = Vec::<Hash>::new();
     for i in 0..wallets.len() {
         for mut j in 0..txs_count {
             if j == i { j += 1; }
             let idx = j % wallets.len();
 
-            wallets[i].send_tx(&wallets[idx].pub_key_pem, rand::random::<f64>() * 100.0);
+            let amount = Amount::from_units(1 + rand::random::<u64>() % Amount::from_gry(10).units());
+            let fee = wallets[i].estimate_fee(&wallets[idx].pub_key_pem);
+            ids.push(wallets[i].send_tx(&wallets[idx].pub_key_pem, amount, fee));
         }
     }
+
+    return ids;
 }
 
 fn shutdown_test_wallets(wallets: Vec<Wallet>) {
@@ -184,16 +550,15 @@ fn shutdown_test_wallets(wallets: Vec<Wallet>) {
     }
 }
 
-fn wait_for_wallets(wallets: &Vec<Wallet>) {
-    loop {
-        let mut idling = false;
-        for wallet in wallets {
-            idling = wallet.is_idling();
-            if !idling { break; }
+fn wait_for_wallets(wallets: &[Wallet]) {
+    let mut idle_checks = 0;
+    while idle_checks < 10 {
+        if wallets.iter().all(|w| w.is_idling()) {
+            idle_checks += 1;
+        } else {
+            idle_checks = 0;
         }
 
-        if idling { break; }
-
         sleep(Duration::from_millis(100));
     }
 }
@@ -0,0 +1,210 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream, Shutdown},
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender, SyncSender, TrySendError}},
+    thread::{self, sleep},
+    time::Duration
+};
+
+use super::{pkg::Package, tcp::{recv, send}};
+
+/// packages per peer waiting to be written (more are dropped)
+const QUEUE_SIZE: usize = 1024;
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(1);
+/// a peer which does not read for that long gets a new connection
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// connects in a row before the writer gives up (and closes the connection)
+const MAX_CONNECTS: usize = 8;
+
+pub enum SendError {
+    /// the peer does not keep up (the package is dropped, see `Connection::is_full`)
+    QueueFull,
+    /// the writer gave up to reconnect (or the peer closed the stream it opened)
+    Closed,
+}
+
+/// long lived connection to a peer, a writer thread empties the queue into the stream
+/// (and reconnects if it breaks) and a reader thread per stream passes the packages on
+pub struct Connection {
+    queue: SyncSender<Package>,
+    /// packages in the queue (the writer counts down)
+    queued: Arc<AtomicUsize>,
+    /// the peer closed the stream it opened
+    closed: Arc<AtomicBool>,
+    /// the peer opened the stream
+    accepted: bool,
+}
+
+impl Connection {
+    /// connects to `addr` in the writer thread (frames of the chain `magic` up to `max_pkg_size` in both directions)
+    pub fn new(addr: SocketAddr, magic: u32, max_pkg_size: usize, inbound: Sender<(SocketAddr, Package)>) -> Connection {
+        let (queue, pkgs) = mpsc::sync_channel::<Package>(QUEUE_SIZE);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let written = Arc::clone(&queued);
+        thread::spawn(move || write_loop(addr, None, pkgs, written, magic, max_pkg_size, inbound));
+        return Connection { queue, queued, closed: Arc::new(AtomicBool::new(false)), accepted: false };
+    }
+
+    /// connection over a stream the peer opened (from `addr`), it is not reopened once it breaks
+    pub fn accept(addr: SocketAddr, stream: TcpStream, magic: u32, max_pkg_size: usize, inbound: Sender<(SocketAddr, Package)>) -> Option<Connection> {
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+        let closed = Arc::new(AtomicBool::new(false));
+        let on_close = Arc::clone(&closed);
+        spawn_reader(stream.try_clone().ok()?, addr, magic, max_pkg_size, inbound.clone(), move || on_close.store(true, Ordering::Release));
+
+        let (queue, pkgs) = mpsc::sync_channel::<Package>(QUEUE_SIZE);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let written = Arc::clone(&queued);
+        thread::spawn(move || write_loop(addr, Some(stream), pkgs, written, magic, max_pkg_size, inbound));
+        return Some(Connection { queue, queued, closed, accepted: true });
+    }
+
+    pub fn is_closed(&self) -> bool {
+        return self.closed.load(Ordering::Acquire);
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        return self.accepted;
+    }
+
+    /// the next package would be dropped
+    pub fn is_full(&self) -> bool {
+        return self.queued.load(Ordering::Acquire) >= QUEUE_SIZE;
+    }
+
+    /// never blocks (the caller may hold the network), so the package is dropped if the queue is full
+    pub fn send(&self, pkg: Package) -> Result<(), SendError> {
+        if self.is_closed() {
+            return Err(SendError::Closed);
+        }
+
+        self.queued.fetch_add(1, Ordering::AcqRel);
+        let res = self.queue.try_send(pkg);
+        if res.is_err() {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+        }
+
+        return match res {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(SendError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => Err(SendError::Closed),
+        };
+    }
+}
+
+/// runs until the connection is dropped (after writing the queued packages) or the peer is unreachable
+fn write_loop(addr: SocketAddr, stream: Option<TcpStream>, pkgs: Receiver<Package>, queued: Arc<AtomicUsize>, magic: u32, max_pkg_size: usize, inbound: Sender<(SocketAddr, Package)>) {
+    // the port of a stream opened by the peer is not the one it listens on
+    let reconnect = stream.is_none();
+    let mut stream = stream;
+
+    for pkg in pkgs.iter() {
+        queued.fetch_sub(1, Ordering::AcqRel);
+
+        loop {
+            if stream.is_none() && reconnect {
+                stream = connect(&addr, magic, max_pkg_size, &inbound);
+            }
+
+            let Some(cur_stream) = &mut stream else {
+                println!("could not connect with {}", addr);
+                return;
+            };
+
+            match send(cur_stream, &pkg, max_pkg_size, magic) {
+                Ok(()) => break,
+                // nothing was written, the stream is still fine
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                    eprintln!("ERROR: could not send {:?} package to {}: {}", pkg.typ, addr, err);
+                    break;
+                }
+                Err(err) => {
+                    println!("lost connection with {}: {}", addr, err);
+                    let _ = cur_stream.shutdown(Shutdown::Both);
+                    stream = None;
+                }
+            }
+        }
+    }
+
+    // the reader of the stream (here and at the peer) stops as well
+    if let Some(stream) = stream {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// retries with exponential backoff, the new stream gets its own reader
+fn connect(addr: &SocketAddr, magic: u32, max_pkg_size: usize, inbound: &Sender<(SocketAddr, Package)>) -> Option<TcpStream> {
+    let mut backoff = MIN_BACKOFF;
+
+    for _ in 0..MAX_CONNECTS {
+        if let Ok(stream) = TcpStream::connect_timeout(addr, CONNECTION_TIMEOUT) {
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            spawn_reader(stream.try_clone().ok()?, *addr, magic, max_pkg_size, inbound.clone(), || {});
+            return Some(stream);
+        }
+
+        sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    return None;
+}
+
+/// reads the packages of `stream` into `inbound` until it is closed (then `on_close` is called),
+/// they come with `addr` (the connection to answer on)
+fn spawn_reader(stream: TcpStream, addr: SocketAddr, magic: u32, max_pkg_size: usize, inbound: Sender<(SocketAddr, Package)>,
+                on_close: impl FnOnce() + Send + 'static) {
+    let mut stream = stream;
+
+    thread::spawn(move || {
+        let _ = stream.set_nonblocking(false);
+
+        loop {
+            match recv(&mut stream, max_pkg_size, magic) {
+                Ok(Some(pkg)) => {
+                    // the receiving node is offline
+                    if inbound.send((addr, pkg)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    println!("stop reading from {}: {}", addr, err);
+                    break;
+                }
+            }
+        }
+
+        let _ = stream.shutdown(Shutdown::Both);
+        on_close();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::mpsc};
+
+    use crate::{test_utils::create_signer, net::pkg::{Package, PackageType, DEFAULT_MAX_PKG_SIZE}, net::tcp::DEFAULT_MAGIC};
+
+    use super::{Connection, SendError, QUEUE_SIZE};
+
+    #[test]
+    fn full_queue() {
+        // nobody listens there, so the writer is stuck reconnecting
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (inbound, _receiver) = mpsc::channel();
+        let connection = Connection::new(addr, DEFAULT_MAGIC, DEFAULT_MAX_PKG_SIZE, inbound);
+
+        let (pub_key, sign_key) = create_signer();
+        let pkg = Package::new(0u64, PackageType::Verack, pub_key, sign_key);
+
+        assert!(!connection.is_full());
+        let sent = (0..QUEUE_SIZE + 2).take_while(|_| connection.send(pkg.clone()).is_ok()).count();
+        assert!(sent >= QUEUE_SIZE);
+        assert!(matches!(connection.send(pkg), Err(SendError::QueueFull)));
+        assert!(connection.is_full());
+    }
+}
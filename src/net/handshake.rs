@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+
+use crate::crypto::Hash;
+
+use super::serialize::Serializer;
+
+/// version of the wire protocol (bumped with every new package type or changed content),
+/// since 2 requests are answered over their connection (and carry no address to answer to),
+/// since 3 proofs are requested in pages
+pub const PROTOCOL_VERSION: u32 = 3;
+/// oldest version this node can still talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// keeps all blocks and answers block, header and proof requests (light wallets do not)
+pub const FEATURE_FULL_NODE: u64 = 1;
+
+/// first package to a peer, it answers with its own version (if it did not send one yet) and a `Verack`
+#[derive(Serializer)]
+pub struct Version {
+    pub version: u32,
+    /// id of the chain spec of the sender
+    pub chain_id: Hash,
+    /// number of blocks (or headers) in the main chain of the sender
+    pub height: usize,
+    /// `FEATURE_*` bits
+    pub features: u64,
+    /// random per node (a node which gets its own nonce is connected to itself)
+    pub nonce: u64,
+    /// where the sender listens (its pub key is the sender of the package)
+    pub addr: SocketAddr,
+}
+
+impl Version {
+    /// checks if the sender can become a peer of the node with `chain_id` and `nonce`
+    pub fn check(&self, chain_id: &Hash, nonce: u64) -> Result<(), &'static str> {
+        if self.nonce == nonce {
+            return Err("connected to itself");
+        }
+
+        if self.version < MIN_PROTOCOL_VERSION {
+            return Err("protocol version is too old");
+        }
+
+        if self.chain_id != *chain_id {
+            return Err("node of another chain");
+        }
+
+        return Ok(());
+    }
+
+    pub fn has_feature(&self, feature: u64) -> bool {
+        return self.features & feature == feature;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::Hash;
+
+    use super::{Version, PROTOCOL_VERSION, FEATURE_FULL_NODE};
+
+    #[test]
+    fn check_version() {
+        let chain_id = Hash::digest(b"chain");
+        let version = Version {
+            version: PROTOCOL_VERSION, chain_id, height: 1, features: FEATURE_FULL_NODE, nonce: 1,
+            addr: "127.0.0.1:8000".parse().unwrap()
+        };
+
+        assert_eq!(version.check(&chain_id, 2), Ok(()));
+        assert!(version.has_feature(FEATURE_FULL_NODE));
+
+        assert!(version.check(&chain_id, 1).is_err());
+        assert!(version.check(&Hash::digest(b"other chain"), 2).is_err());
+        assert!(Version { version: 0, ..version }.check(&chain_id, 2).is_err());
+
+        // newer nodes are fine (they do not send us what we do not know)
+        let version = Version { version: PROTOCOL_VERSION + 1, features: 0, ..version };
+        assert_eq!(version.check(&chain_id, 2), Ok(()));
+        assert!(!version.has_feature(FEATURE_FULL_NODE));
+    }
+}
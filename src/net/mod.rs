@@ -3,3 +3,6 @@ pub mod pkg;
 pub mod serialize;
 pub mod network;
 pub mod node;
+pub mod sync;
+pub mod handshake;
+pub mod connection;
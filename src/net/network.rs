@@ -1,107 +1,316 @@
-use std::{net::{TcpStream, SocketAddr, IpAddr, Ipv4Addr}, time::Duration, collections::HashMap, fmt::Display, thread::sleep};
+use std::{
+    net::{TcpStream, SocketAddr},
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::mpsc::{self, Sender, Receiver},
+    time::{Duration, Instant}
+};
 
+use rand::random;
 use rsa::{pss::BlindedSigningKey, sha2::Sha256};
 
-use super::{pkg::{Package, PackageType}, tcp::send, node::Node};
+use crate::{blockchain::ChainSpec, crypto::Hash};
 
-const CONNECTION_TIMEOUT: Duration = Duration::from_secs(1);
-const RESPONSE_SLEEP: Duration = Duration::from_millis(100);
-const RESPONSE_TIMEOUT: Duration = Duration::from_secs(7);
+use super::{
+    pkg::{Package, PackageType, get_max_content_size},
+    node::Node,
+    handshake::{Version, PROTOCOL_VERSION},
+    connection::{Connection, SendError}
+};
+
+/// max nodes per `NodesRes` (with keys of up to 4096 bits they still fit into a package)
+const MAX_NODES_RES: usize = 1000;
+/// streams opened by other nodes (every one has a reader and a writer thread)
+const MAX_INBOUND: usize = 64;
+/// an opened stream without a version after that long is closed
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// node which sent a compatible version
+struct Peer {
+    /// where the peer listens (for other nodes)
+    addr: SocketAddr,
+    /// connection its version came over (everything to the peer goes there)
+    conn: SocketAddr,
+    /// protocol version of the peer
+    version: u32,
+    /// it accepted our version as well (only then it gets broadcasts)
+    acked: bool,
+}
 
 pub struct Network {
-    nodes: HashMap<String, u16> // TODO: add ip later
+    /// by pub key
+    peers: HashMap<String, Peer>,
+    /// where the handshakes start when going online
+    master_nodes: Vec<SocketAddr>,
+    /// connections which got our version but did not send one yet
+    greeted: HashSet<SocketAddr>,
+    /// streams opened by other nodes which did not send a version yet (by when they were accepted)
+    pending: HashMap<SocketAddr, Instant>,
+    /// connections which dropped chain data since their queue was full (they get our tip once it has room)
+    stale: HashSet<SocketAddr>,
+    /// magic of the frames of the chain
+    magic: u32,
+    /// biggest package of the chain (sent or received)
+    max_pkg_size: usize,
+    chain_id: Hash,
+    /// `FEATURE_*` bits of this node
+    features: u64,
+    /// random id of this node (to detect connections to itself)
+    nonce: u64,
+    /// by the address the other node listens on or (if it opened the connection) the one it connected from
+    connections: HashMap<SocketAddr, Connection>,
+    /// the readers of all connections send their packages here (with the connection they came over)
+    inbound: Sender<(SocketAddr, Package)>,
+    /// until the receiving thread takes it
+    receiver: Option<Receiver<(SocketAddr, Package)>>,
 }
 
 impl Network {
-    pub fn new(master_nodes: &Vec<Node>) -> Network {
-        let mut nodes = HashMap::<String, u16>::new();
-        for node in master_nodes {
-            nodes.insert(node.pub_key.to_string(), node.port);
+    pub fn new(master_nodes: &[Node], spec: &ChainSpec, features: u64) -> Network {
+        let mut network = Self::new_empty(spec, features);
+        network.master_nodes = master_nodes.iter().map(|node| node.addr).collect();
+        return network;
+    }
+
+    pub fn new_empty(spec: &ChainSpec, features: u64) -> Network {
+        let (inbound, receiver) = mpsc::channel::<(SocketAddr, Package)>();
+        return Network {
+            peers: HashMap::new(),
+            master_nodes: Vec::new(),
+            greeted: HashSet::new(),
+            pending: HashMap::new(),
+            stale: HashSet::new(),
+            magic: spec.magic,
+            max_pkg_size: spec.max_pkg_size,
+            chain_id: spec.gen_id(),
+            features,
+            nonce: random::<u64>(),
+            connections: HashMap::new(),
+            inbound,
+            receiver: Some(receiver),
+        };
+    }
+
+    /// the packages of all connections (for the receiving thread of the node, only once)
+    pub fn take_receiver(&mut self) -> Option<Receiver<(SocketAddr, Package)>> {
+        return self.receiver.take();
+    }
+
+    /// keeps a stream opened by another node (its packages arrive with the address it connected from)
+    pub fn accept(&mut self, stream: TcpStream) {
+        let Ok(addr) = stream.peer_addr() else {
+            return;
+        };
+
+        self.drop_silent();
+        if self.connections.values().filter(|connection| connection.is_accepted()).count() >= MAX_INBOUND {
+            println!("refuse connection of {} (too many inbound connections)", addr);
+            return;
         }
 
-        return Network{ nodes };
+        if let Some(connection) = Connection::accept(addr, stream, self.magic, self.max_pkg_size, self.inbound.clone()) {
+            self.connections.insert(addr, connection);
+            self.pending.insert(addr, Instant::now());
+        }
     }
 
-    pub fn new_empty() -> Network {
-        return Network{ nodes: HashMap::<String, u16>::new() };
+    /// closes the streams opened by other nodes which did not send a version in time
+    /// (and forgets the closed ones, they would pile up otherwise)
+    pub fn drop_silent(&mut self) {
+        let now = Instant::now();
+        let silent = self.pending.iter()
+            .filter(|(_, accepted)| now.duration_since(**accepted) > HANDSHAKE_TIMEOUT)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<SocketAddr>>();
+
+        for addr in silent {
+            println!("drop connection of {} (no version in time)", addr);
+            self.pending.remove(&addr);
+            self.connections.remove(&addr);
+        }
+
+        self.connections.retain(|_, connection| !connection.is_closed());
     }
 
-    pub fn go_offline(&self, pub_key: String, port: u16, sign_key: BlindedSigningKey::<Sha256>) {
-        let node = Node {pub_key: pub_key.clone(), port, online: false};
+    pub fn go_offline(&mut self, pub_key: String, addr: SocketAddr, sign_key: BlindedSigningKey::<Sha256>) {
+        let node = Node { pub_key: pub_key.clone(), addr, online: false };
         let pkg = Package::new(node, PackageType::Status, pub_key, sign_key);
         self.broadcast(pkg);
     }
 
-    pub fn go_online(&self, pub_key: String, port: u16, sign_key: BlindedSigningKey::<Sha256>) -> Result<(), &str> {
-        let node = Node {pub_key: pub_key.clone(), port, online: true};
-        let pkg = Package::new(node, PackageType::Status, pub_key, sign_key);
-        self.broadcast(pkg);
+    /// starts the handshakes with the master nodes (their versions and veracks arrive at the receiver)
+    pub fn go_online(&mut self, pub_key: String, addr: SocketAddr, sign_key: BlindedSigningKey::<Sha256>, height: usize) {
+        for master_node in self.master_nodes.clone() {
+            self.greet(&master_node, pub_key.clone(), addr, sign_key.clone(), height);
+        }
+    }
+
+    /// sends our version over the connection `peer_addr` (`height` is the length of our main chain)
+    pub fn greet(&mut self, peer_addr: &SocketAddr, pub_key: String, addr: SocketAddr,
+                 sign_key: BlindedSigningKey::<Sha256>, height: usize) {
+        let version = Version {
+            version: PROTOCOL_VERSION,
+            chain_id: self.chain_id,
+            height,
+            features: self.features,
+            nonce: self.nonce,
+            addr
+        };
+
+        self.greeted.insert(*peer_addr);
+        self.send_to(peer_addr, Package::new(version, PackageType::Version, pub_key, sign_key));
+    }
+
+    /// registers the sender of a compatible version which came over the connection `conn` (and drops it otherwise),
+    /// returns if it still has to get our version
+    pub fn add_peer(&mut self, pub_key: String, version: &Version, conn: SocketAddr) -> Result<bool, &'static str> {
+        let greeted = self.greeted.remove(&conn);
+        self.pending.remove(&conn);
+
+        if let Err(err) = version.check(&self.chain_id, self.nonce) {
+            self.peers.remove(&pub_key);
+            self.connections.remove(&conn);
+            return Err(err);
+        }
 
-        const MAX_ITER: usize = (RESPONSE_TIMEOUT.as_millis() / RESPONSE_SLEEP.as_millis()) as usize;
+        self.peers.insert(pub_key, Peer { addr: version.addr, conn, version: version.version, acked: false });
+        return Ok(!greeted);
+    }
+
+    /// the peer accepted our version (the verack has our nonce)
+    pub fn ack(&mut self, pub_key: &str, nonce: u64) {
+        if nonce != self.nonce {
+            return;
+        }
 
-        for _ in 0..MAX_ITER {
-            if !self.nodes.is_empty() { return Ok(()); }
-            sleep(RESPONSE_SLEEP); 
+        if let Some(peer) = self.peers.get_mut(pub_key) {
+            peer.acked = true;
         }
+    }
 
-        return Err("no response");
+    pub fn is_peer(&self, pub_key: &str) -> bool {
+        return self.peers.contains_key(pub_key);
     }
 
-    pub fn register(&mut self, pub_key: String, port: u16) {
-        self.nodes.insert(pub_key, port);
+    /// true if `pub_key` is a peer, its connection `conn` stays open then (peers reconnect without a new version)
+    pub fn check_peer(&mut self, pub_key: &str, conn: &SocketAddr) -> bool {
+        if !self.is_peer(pub_key) {
+            return false;
+        }
+
+        self.pending.remove(conn);
+        return true;
     }
 
-    pub fn contains(&mut self, pub_key: &String) -> bool {
-        return self.nodes.get(pub_key) == None;
+    /// peer or in the middle of a handshake
+    pub fn knows(&self, pub_key: &str, addr: &SocketAddr) -> bool {
+        return self.peers.contains_key(pub_key) || self.greeted.contains(addr);
     }
 
-    pub fn deregister(&mut self, pub_key: String) {
-        self.nodes.remove(&pub_key);
+    pub fn deregister(&mut self, pub_key: &str) {
+        if let Some(peer) = self.peers.remove(pub_key) {
+            self.connections.remove(&peer.conn);
+        }
     }
 
-    pub fn to_nodes(&self) -> Vec<Node> {
-        return self.nodes.iter()
-            .map(|(pub_key, port)| Node { pub_key: pub_key.clone(), port: *port, online: true })
+    /// peers except `pub_key` (for a `NodesRes` to it, so no more than fit into a package)
+    pub fn to_nodes(&self, pub_key: &str) -> Vec<Node> {
+        return self.peers.iter()
+            .filter(|(peer_key, _)| *peer_key != pub_key)
+            .map(|(peer_key, peer)| Node { pub_key: peer_key.clone(), addr: peer.addr, online: true })
+            .take(MAX_NODES_RES)
             .collect();
     }
 
-    pub fn broadcast(&self, pkg: Package) {
-        for (_, port) in &self.nodes {
-            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), *port);
-            let stream = TcpStream::connect_timeout(&addr, CONNECTION_TIMEOUT);
+    /// sends to the acked peers which know the type of the package
+    pub fn broadcast(&mut self, pkg: Package) {
+        let conns = self.peers.values()
+            .filter(|peer| peer.acked && peer.version >= pkg.typ.get_version())
+            .map(|peer| peer.conn)
+            .collect::<Vec<SocketAddr>>();
 
-            if let Ok(stream) = stream {
-                send(stream, pkg.clone());
-            } else {
-                println!("could not connect with {}", addr);
-            }
+        for conn in conns {
+            self.send_to(&conn, pkg.clone());
         }
     }
 
-    pub fn broadcast_forward(&self, pkg: Package) {
-        for (_, port) in &self.nodes {
-            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), *port);
-            let stream = TcpStream::connect_timeout(&addr, CONNECTION_TIMEOUT);
+    /// queues the package for the connection `addr` (a new one to a node listening there if there is none)
+    pub fn send_to(&mut self, addr: &SocketAddr, pkg: Package) {
+        let connection = self.connections.entry(*addr)
+            .or_insert_with(|| Connection::new(*addr, self.magic, self.max_pkg_size, self.inbound.clone()));
 
-            if let Ok(stream) = stream {
-                let mut forwarded_pkg = pkg.clone();
-                forwarded_pkg.is_forwarded = true;
-                send(stream, forwarded_pkg);
-            } else {
-                println!("could not connect with {}", addr);
+        let typ = pkg.typ;
+        match connection.send(pkg) {
+            Ok(()) => {}
+            Err(SendError::QueueFull) => {
+                println!("drop {:?} package to {} (queue is full)", typ, addr);
+                // nobody asks for a dropped block again, so the peer has to re-sync from our tip
+                if typ.is_chain_data() {
+                    self.stale.insert(*addr);
+                }
+            }
+            Err(SendError::Closed) => {
+                // the writer could not reconnect, so the node is offline
+                println!("drop node {} (connection is closed)", addr);
+                self.connections.remove(addr);
+                self.peers.retain(|_, peer| peer.conn != *addr);
             }
         }
     }
 
+    /// connections which dropped chain data and have room in their queue again (they need our tip to re-sync)
+    pub fn take_stale(&mut self) -> Vec<SocketAddr> {
+        self.stale.retain(|addr| self.connections.contains_key(addr));
+        let ready = self.stale.iter()
+            .filter(|addr| !self.connections[addr].is_full())
+            .copied()
+            .collect::<Vec<SocketAddr>>();
+
+        for addr in &ready {
+            self.stale.remove(addr);
+        }
+        return ready;
+    }
+
+    /// biggest content of a package to the peers (responses are cut to it)
+    pub fn get_max_content_size(&self) -> usize {
+        return get_max_content_size(self.max_pkg_size);
+    }
+
+    /// number of acked peers
     pub fn get_len(&self) -> usize {
-        return self.nodes.len();
+        return self.peers.values().filter(|peer| peer.acked).count();
     }
 }
 
 impl Display for Network {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "{}", self.nodes.iter()
-                        .map(|(_, port)| format!("127.0.0.1:{}\n", port))
+        return write!(f, "{}", self.peers.values()
+                        .map(|peer| format!("{} (version: {}{})\n", peer.addr, peer.version, if peer.acked { "" } else { ", not acked" }))
                         .collect::<String>());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::blockchain::ChainSpec;
+
+    use super::{Network, MAX_INBOUND};
+
+    #[test]
+    fn max_inbound() {
+        let mut network = Network::new_empty(&ChainSpec::default(), 0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let streams = (0..=MAX_INBOUND).map(|_| TcpStream::connect(addr).unwrap()).collect::<Vec<TcpStream>>();
+        for _ in &streams {
+            network.accept(listener.accept().unwrap().0);
+        }
+
+        assert_eq!(network.connections.len(), MAX_INBOUND);
+        assert_eq!(network.pending.len(), MAX_INBOUND);
+    }
+}
@@ -1,91 +1,116 @@
 use std::{mem::size_of, fmt::Display};
 
 use rsa::{
-    pss::{Signature, VerifyingKey, BlindedSigningKey},
-    sha2::Sha256, RsaPublicKey,
-    pkcs8::DecodePublicKey,
-    signature::{Verifier, RandomizedSigner}
+    pss::{Signature, BlindedSigningKey},
+    sha2::Sha256,
+    signature::RandomizedSigner
 };
 
-use crate::{blockchain::Transaction, crypto::{RSA_BYTES, RSA_PEM_SIZE}};
+use crate::{blockchain::{Transaction, DoubleSign}, crypto::{RSA_BYTES, verify_sign}};
 
-use super::{serialize::Serializer, node::Node};
+use super::{serialize::{Serializer, DecodeError, to_bytes}, node::Node, handshake::Version, sync::{Tip, BlocksReq, BlocksRes, HeadersRes, ProofsReq, ProofsRes}};
 
-pub const PKG_CONTENT_SIZE: usize = 9000;                   // TODO: smaller
-pub const PKG_SIZE: usize = size_of::<PackageType>() +
-                            PKG_CONTENT_SIZE +
-                            RSA_PEM_SIZE + size_of::<usize>() +
-                            RSA_BYTES + size_of::<usize>() +
-                            size_of::<bool>();
+/// biggest serialized package (the payload of a frame) if the chain spec does not set another one,
+/// receivers drop the connection on bigger ones
+pub const DEFAULT_MAX_PKG_SIZE: usize = 1 << 20;
+/// smallest max package size of a chain (a block with `MAX_BLOCK_TXS` txs and `MAX_BLOCK_EVIDENCE` evidence fits)
+pub const MIN_PKG_SIZE: usize = 1 << 18;
+/// room for the sender (the pem of its key) and the signature next to the content
+const PKG_OVERHEAD: usize = 4096;
+
+/// biggest serialized content of a package of at most `max_pkg_size`
+pub fn get_max_content_size(max_pkg_size: usize) -> usize {
+    return max_pkg_size.saturating_sub(PKG_OVERHEAD);
+}
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serializer)]
 pub enum PackageType {
-    Tx, Status, NodesRes, Block
+    Tx, Status, NodesRes, Block, TipReq, TipRes, BlocksReq, BlocksRes, HeadersReq, HeadersRes, ProofsReq, ProofsRes, Evidence,
+    Version, Verack
+}
+
+impl PackageType {
+    /// first protocol version with this type (peers of older versions do not get it, all types are in the first one so far)
+    pub fn get_version(&self) -> u32 {
+        return 1;
+    }
+
+    /// blocks (or what leads to them), a peer which misses one of those has to re-sync
+    pub fn is_chain_data(&self) -> bool {
+        return matches!(self, PackageType::Block | PackageType::TipRes | PackageType::BlocksRes | PackageType::HeadersRes);
+    }
 }
 
 #[derive(Clone)]
 pub struct Package {
     pub typ: PackageType,
-    pub content: [u8; PKG_CONTENT_SIZE],
+    pub content: Vec<u8>,
     pub sender: String,
     sign: Signature,
-    pub is_forwarded: bool
 }
 
 impl Package {
     pub fn new<T: Serializer>(content: T, typ: PackageType, pub_key: String,
                               sign_key: BlindedSigningKey<Sha256>) -> Package {
-        let mut content_bytes = [0u8; PKG_CONTENT_SIZE];
-        content.serialize(&mut content_bytes);
+        let content_bytes = to_bytes(&content);
 
         let mut rng = rand::thread_rng();
         let sign = sign_key.sign_with_rng(&mut rng, &content_bytes);
 
-        return Package{ typ, content: content_bytes, sender: pub_key, sign, is_forwarded: false };
+        return Package{ typ, content: content_bytes, sender: pub_key, sign };
     }
 
-    pub fn deserialize(bytes: [u8; PKG_SIZE]) -> Self {
+    /// the type is not part of the bytes (it is sent in the frame header)
+    pub fn deserialize(typ: PackageType, bytes: &[u8]) -> Result<Self, DecodeError> {
         let mut start: usize = 0;
 
-        let (size, typ) = PackageType::deserialize(&bytes[start..]);
+        let (size, content) = Vec::<u8>::deserialize(&bytes[start..])?;
         start += size;
 
-        let mut content = [0u8; PKG_CONTENT_SIZE];
-        content.copy_from_slice(&bytes[start..start+PKG_CONTENT_SIZE]);
-        start += PKG_CONTENT_SIZE;
-
-        let (size, sender) = String::deserialize(&bytes[start..]);
+        let (size, sender) = String::deserialize(&bytes[start..])?;
         start += size;
 
-        let (size, sign) = Signature::deserialize(&bytes[start..]);
+        let (size, sign) = Signature::deserialize(&bytes[start..])?;
         start += size;
 
-        let is_forwarded = bool::deserialize(&bytes[start..]).1;
+        if start != bytes.len() {
+            return Err(DecodeError::Invalid("trailing bytes after package"));
+        }
 
-        return Package { typ, content, sender, sign, is_forwarded };
+        return Ok(Package { typ, content, sender, sign });
     }
 
-    pub fn serialize(&self) -> [u8; PKG_SIZE] {
-        let mut buf = [0u8; PKG_SIZE];
+    /// decodes the content (which has to be used completely)
+    pub fn decode<T: Serializer>(&self) -> Result<T, DecodeError> {
+        let (size, value) = T::deserialize(&self.content)?;
+        if size != self.content.len() {
+            return Err(DecodeError::Invalid("trailing bytes after content"));
+        }
+
+        return Ok(value);
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.get_size()];
         let mut start: usize = 0;
 
-        start += self.typ.serialize(&mut buf[start..]);
-        buf[start..start+PKG_CONTENT_SIZE].copy_from_slice(&self.content);
-        start += PKG_CONTENT_SIZE;
+        start += self.content.serialize(&mut buf[start..]);
         start += self.sender.serialize(&mut buf[start..]);
-        start += self.sign.serialize(&mut buf[start..]); 
-        self.is_forwarded.serialize(&mut buf[start..]);
+        self.sign.serialize(&mut buf[start..]);
 
         return buf;
     }
 
-    pub fn verify(&self) -> bool {
-        let pub_key = RsaPublicKey::from_public_key_pem(&self.sender)
-            .expect("ERROR: could not get public key from pem");
-        let verify_key = VerifyingKey::<Sha256>::from(pub_key);
+    /// size of the serialized package (see `DEFAULT_MAX_PKG_SIZE`)
+    pub fn get_size(&self) -> usize {
+        return size_of::<u32>() + self.content.len() +
+               size_of::<u32>() + self.sender.len() +
+               size_of::<u32>() + RSA_BYTES;
+    }
 
-        if let Ok(..) = verify_key.verify(&self.content, &self.sign) {
+    pub fn verify(&self) -> bool {
+        if verify_sign(&self.sender, &self.content, &self.sign) {
             return true;
         } else {
             println!("ERROR: invalid transaction (corrupted)");
@@ -98,27 +123,71 @@ impl Display for Package {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let content_str = match self.typ {
             PackageType::Tx => {
-                Transaction::deserialize(&self.content).1.to_string()
+                self.decode::<Transaction>().map(|tx| tx.to_string())
             }
 
             PackageType::Block => {
-                "BLOCK PACKAGE CONTENT\n".to_string()
+                Ok("BLOCK PACKAGE CONTENT\n".to_string())
+            }
+
+            PackageType::TipReq => {
+                self.decode::<usize>().map(|height| format!("Tip request (height: {})\n", height))
+            }
+
+            PackageType::TipRes => {
+                self.decode::<Tip>().map(|tip| format!("Tip: {} (height: {})\n", tip.hash, tip.height))
+            }
+
+            PackageType::BlocksReq => {
+                self.decode::<BlocksReq>().map(|req| format!("Blocks from round {}\n", req.from))
+            }
+
+            PackageType::BlocksRes => {
+                self.decode::<BlocksRes>().map(|res| format!("{} blocks from round {}\n", res.blocks.len(), res.from))
+            }
+
+            PackageType::HeadersReq => {
+                self.decode::<BlocksReq>().map(|req| format!("Headers from round {}\n", req.from))
+            }
+
+            PackageType::HeadersRes => {
+                self.decode::<HeadersRes>().map(|res| format!("{} headers from round {}\n", res.headers.len(), res.from))
+            }
+
+            PackageType::ProofsReq => {
+                self.decode::<ProofsReq>().map(|req| format!("Proofs from {} for\n{}", req.from, req.pub_key))
+            }
+
+            PackageType::ProofsRes => {
+                self.decode::<ProofsRes>().map(|res| format!("{} tx proofs from {}\n", res.proofs.len(), res.from))
+            }
+
+            PackageType::Evidence => {
+                self.decode::<DoubleSign>().map(|evidence| format!("Double sign in round {} by\n{}", evidence.first.round, evidence.validator))
+            }
+
+            PackageType::Version => {
+                self.decode::<Version>().map(|version| format!("Version {} of {} (height: {}, features: {:#x})\n",
+                    version.version, version.addr, version.height, version.features))
+            }
+
+            PackageType::Verack => {
+                self.decode::<u64>().map(|nonce| format!("Verack of nonce {}\n", nonce))
             }
 
             PackageType::NodesRes => {
-                let nodes = Vec::<Node>::deserialize(&self.content).1;
-                nodes.iter().map(|node| node.to_string() + "\n").collect::<String>()
+                self.decode::<Vec<Node>>().map(|nodes| nodes.iter().map(|node| node.to_string() + "\n").collect::<String>())
             }
 
             PackageType::Status => {
-                let node = Node::deserialize(&self.content).1;
-                if node.online {
+                self.decode::<Node>().map(|node| if node.online {
                     "Register wallet:\n".to_string() + &node.pub_key
                 } else {
                     "Deregister wallet:\n".to_string() + &node.pub_key
-                }
+                })
             }
         };
+        let content_str = content_str.unwrap_or_else(|err| format!("INVALID CONTENT: {}\n", err));
 
         return write!(f, "TYPE: {:?} {{\n{}}}\n", self.typ, content_str);
     }
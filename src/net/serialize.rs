@@ -1,165 +1,384 @@
-use std::mem::{transmute, size_of, size_of_val};
+use std::{fmt::Display, mem::size_of, net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr}};
 
 use rsa::pss::Signature;
 
-use super::pkg::PackageType;
+pub use greychain_derive::Serializer;
 
-pub trait Serializer {
-    fn serialize(&self, dst: &mut [u8]) -> usize;
-    fn deserialize(bytes: &[u8]) -> (usize, Self);
+/// why bytes could not be decoded (all numbers are little endian with fixed widths)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// input ended before the value was complete
+    UnexpectedEnd,
+    /// bytes do not form a valid value
+    Invalid(&'static str),
 }
 
-impl Serializer for String {
-    fn serialize(&self, dst: &mut [u8]) -> usize {
-        let mut start = 0;
-        start += self.len().serialize(dst);
-        dst[start..start+self.len()].copy_from_slice(self.as_bytes());
-        return start+self.len();
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::Invalid(reason) => write!(f, "invalid value: {}", reason),
+        };
     }
+}
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        let mut start: usize = 0;
+pub trait Serializer: Sized {
+    /// `dst` has to have room for `get_size` bytes
+    fn serialize(&self, dst: &mut [u8]) -> usize;
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError>;
+    /// number of bytes `serialize` writes
+    fn get_size(&self) -> usize;
+}
 
-        let (size, str_len) = usize::deserialize(bytes);
-        start += size;
+/// serializes into a buffer of exactly the size of the value
+pub fn to_bytes<T: Serializer>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![0u8; value.get_size()];
+    let len = value.serialize(&mut bytes);
+    debug_assert_eq!(len, bytes.len());
+    return bytes;
+}
 
-        return (start+str_len, String::from_utf8_lossy(&bytes[start..start+str_len]).to_string());
-    }
+/// first `len` bytes of the input (fails if there are fewer)
+pub fn take(bytes: &[u8], len: usize) -> Result<&[u8], DecodeError> {
+    return bytes.get(..len).ok_or(DecodeError::UnexpectedEnd);
+}
+
+macro_rules! impl_int_serializer {
+    ($($typ:ty),*) => {$(
+        impl Serializer for $typ {
+            fn serialize(&self, dst: &mut [u8]) -> usize {
+                const SIZE: usize = std::mem::size_of::<$typ>();
+                dst[..SIZE].copy_from_slice(&self.to_le_bytes());
+                return SIZE;
+            }
+
+            fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+                const SIZE: usize = std::mem::size_of::<$typ>();
+                return Ok((SIZE, Self::from_le_bytes(take(bytes, SIZE)?.try_into().unwrap())));
+            }
+
+            fn get_size(&self) -> usize {
+                return std::mem::size_of::<$typ>();
+            }
+        }
+    )*};
 }
 
-impl Serializer for u64 {
+impl_int_serializer!(u8, u16, u32, u64, u128);
+
+/// always 8 bytes, independent of the width of usize on the host
+impl Serializer for usize {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<u64>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<u64, [u8; SIZE]>(*self) });
-        return SIZE;
+        return (*self as u64).serialize(dst);
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
-        };
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let (size, value) = u64::deserialize(bytes)?;
+        let value = usize::try_from(value).map_err(|_| DecodeError::Invalid("usize out of range"))?;
+        return Ok((size, value));
+    }
+
+    fn get_size(&self) -> usize {
+        return size_of::<u64>();
     }
 }
 
-impl Serializer for u128 {
+impl Serializer for bool {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<u128>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<u128, [u8; SIZE]>(*self) });
-        return SIZE
+        return (*self as u8).serialize(dst);
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        return match u8::deserialize(bytes)? {
+            (size, 0) => Ok((size, false)),
+            (size, 1) => Ok((size, true)),
+            _ => Err(DecodeError::Invalid("bool is neither 0 nor 1")),
         };
     }
+
+    fn get_size(&self) -> usize {
+        return size_of::<u8>();
+    }
 }
 
-impl Serializer for u16 {
+/// length (u32) followed by the items
+impl<T: Serializer> Serializer for Vec<T> {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<u16>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<u16, [u8; SIZE]>(*self) });
-        return SIZE;
+        let mut start = 0;
+
+        assert!(self.len() <= u32::MAX as usize);
+        start += (self.len() as u32).serialize(&mut dst[start..]);
+        for item in self {
+            start += item.serialize(&mut dst[start..]);
+        }
+
+        return start;
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
-        };
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let mut start = 0;
+
+        let (size, len) = u32::deserialize(bytes)?;
+        start += size;
+
+        // the claimed length is not trusted, so never reserve more memory than the input has bytes
+        let mut items = Vec::<T>::with_capacity((len as usize).min(bytes.len() / size_of::<T>().max(1)));
+        for _ in 0..len {
+            let (size, item) = T::deserialize(&bytes[start..])?;
+            start += size;
+            items.push(item);
+        }
+
+        return Ok((start, items));
+    }
+
+    fn get_size(&self) -> usize {
+        return size_of::<u32>() + self.iter().map(|item| item.get_size()).sum::<usize>();
     }
 }
 
-impl Serializer for u8 {
+/// tag (0 or 1) followed by the value
+impl<T: Serializer> Serializer for Option<T> {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        dst[0] = *self;
-        return size_of::<Self>();
+        return match self {
+            None => false.serialize(dst),
+            Some(value) => true.serialize(dst) + value.serialize(&mut dst[1..]),
+        };
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let (size, is_some) = bool::deserialize(bytes)?;
+        if !is_some {
+            return Ok((size, None));
+        }
+
+        let (value_size, value) = T::deserialize(&bytes[size..])?;
+        return Ok((size + value_size, Some(value)));
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return (size_of::<Self>(), bytes[0]);
+    fn get_size(&self) -> usize {
+        return size_of::<u8>() + self.as_ref().map_or(0, |value| value.get_size());
     }
 }
 
-impl Serializer for PackageType {
+/// items without a length
+impl<T: Serializer, const N: usize> Serializer for [T; N] {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<PackageType>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<PackageType, [u8; SIZE]>(*self) });
-        return SIZE;
+        let mut start = 0;
+        for item in self {
+            start += item.serialize(&mut dst[start..]);
+        }
+
+        return start;
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const PackageType;
-            (size_of::<Self>(), (*ptr).clone())
-        };
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let mut start = 0;
+
+        let mut items = Vec::<T>::with_capacity(N);
+        for _ in 0..N {
+            let (size, item) = T::deserialize(&bytes[start..])?;
+            start += size;
+            items.push(item);
+        }
+
+        return Ok((start, items.try_into().ok().expect("array has N items")));
+    }
+
+    fn get_size(&self) -> usize {
+        return self.iter().map(|item| item.get_size()).sum();
     }
 }
 
-impl Serializer for usize {
+macro_rules! impl_tuple_serializer {
+    ($(($($typ:ident $index:tt),+)),*) => {$(
+        impl<$($typ: Serializer),+> Serializer for ($($typ,)+) {
+            fn serialize(&self, dst: &mut [u8]) -> usize {
+                let mut start = 0;
+                $(start += self.$index.serialize(&mut dst[start..]);)+
+                return start;
+            }
+
+            #[allow(non_snake_case)]
+            fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+                let mut start = 0;
+                $(
+                    let (size, $typ) = $typ::deserialize(&bytes[start..])?;
+                    start += size;
+                )+
+                return Ok((start, ($($typ,)+)));
+            }
+
+            fn get_size(&self) -> usize {
+                let mut size = 0;
+                $(size += self.$index.get_size();)+
+                return size;
+            }
+        }
+    )*};
+}
+
+impl_tuple_serializer!((A 0), (A 0, B 1), (A 0, B 1, C 2), (A 0, B 1, C 2, D 3));
+
+impl Serializer for String {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<usize>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<usize, [u8; SIZE]>(*self) });
-        return SIZE;
+        let mut start = 0;
+
+        assert!(self.len() <= u32::MAX as usize);
+        start += (self.len() as u32).serialize(&mut dst[start..]);
+        dst[start..start+self.len()].copy_from_slice(self.as_bytes());
+
+        return start+self.len();
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
-        };
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let (size, str_bytes) = Vec::<u8>::deserialize(bytes)?;
+        let str = String::from_utf8(str_bytes).map_err(|_| DecodeError::Invalid("string is not utf-8"))?;
+        return Ok((size, str));
+    }
+
+    fn get_size(&self) -> usize {
+        return size_of::<u32>() + self.len();
     }
 }
 
-impl Serializer for f64 {
+/// 4 or 6, the ip (4 or 16 bytes) and the port
+impl Serializer for SocketAddr {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<f64>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<f64, [u8; SIZE]>(*self) });
-        return SIZE;
+        let mut start = 0;
+
+        match self.ip() {
+            IpAddr::V4(ip) => {
+                start += 4u8.serialize(&mut dst[start..]);
+                start += ip.octets().serialize(&mut dst[start..]);
+            }
+            IpAddr::V6(ip) => {
+                start += 6u8.serialize(&mut dst[start..]);
+                start += ip.octets().serialize(&mut dst[start..]);
+            }
+        }
+        start += self.port().serialize(&mut dst[start..]);
+
+        return start;
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let mut start = 0;
+
+        let (size, version) = u8::deserialize(bytes)?;
+        start += size;
+
+        let ip = match version {
+            4 => {
+                let (size, octets) = <[u8; 4]>::deserialize(&bytes[start..])?;
+                start += size;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let (size, octets) = <[u8; 16]>::deserialize(&bytes[start..])?;
+                start += size;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(DecodeError::Invalid("unknown ip version")),
         };
+
+        let (size, port) = u16::deserialize(&bytes[start..])?;
+        start += size;
+
+        return Ok((start, SocketAddr::new(ip, port)));
+    }
+
+    fn get_size(&self) -> usize {
+        let ip_size = if self.is_ipv4() { 4 } else { 16 };
+        return size_of::<u8>() + ip_size + size_of::<u16>();
     }
 }
 
-impl Serializer for bool {
+impl Serializer for Signature {
     fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<bool>();
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<bool, [u8; SIZE]>(*self) });
-        return SIZE;
+        return Box::<[u8]>::from(self.clone()).to_vec().serialize(dst);
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        return unsafe {
-            let ptr = bytes.as_ptr() as *const Self;
-            (size_of::<Self>(), (*ptr).clone())
-        };
+    fn deserialize(bytes: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let (size, sign_bytes) = Vec::<u8>::deserialize(bytes)?;
+        let sign = Signature::try_from(sign_bytes.as_slice()).map_err(|_| DecodeError::Invalid("invalid signature"))?;
+        return Ok((size, sign));
+    }
+
+    fn get_size(&self) -> usize {
+        return size_of::<u32>() + Box::<[u8]>::from(self.clone()).len();
     }
 }
 
-impl Serializer for Signature {
-    fn serialize(&self, dst: &mut [u8]) -> usize {
-        const SIZE: usize = size_of::<usize>();
+#[cfg(test)]
+mod tests {
+    use crate::{blockchain::{Transaction, Amount}, crypto::Hash, test_utils::create_signer};
+
+    use std::net::SocketAddr;
+
+    use super::{Serializer, DecodeError};
 
-        let sign_as_bytes = Box::<[u8]>::from(self.clone());
-        let sign_size = size_of_val(&*sign_as_bytes);
-        dst[..SIZE].copy_from_slice(unsafe { &transmute::<usize, [u8; SIZE]>(sign_size) });
-        dst[SIZE..SIZE+sign_size].copy_from_slice(&*sign_as_bytes);
+    #[test]
+    fn little_endian_and_bounds_checked() {
+        let mut bytes = [0u8; 16];
+        assert_eq!(0x0102usize.serialize(&mut bytes), 8);
+        assert_eq!(bytes[..8], [2, 1, 0, 0, 0, 0, 0, 0]);
 
-        return SIZE+sign_size;
+        assert_eq!(u64::deserialize(&bytes[..7]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(bool::deserialize(&[2]), Err(DecodeError::Invalid("bool is neither 0 nor 1")));
+        assert_eq!(String::deserialize(&[0xff, 0xff, 0xff, 0xff, b'a']), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(String::deserialize(&[2, 0, 0, 0, b'h', b'i']), Ok((6, "hi".to_string())));
+
+        let (payer, sign_key) = create_signer();
+        let tx = Transaction::new(&Hash::ZERO, &payer, &"payee".to_string(), 0, Amount::from_gry(1), Amount::ZERO, &sign_key);
+
+        let mut bytes = vec![0u8; 2048];
+        let len = tx.serialize(&mut bytes);
+        assert_eq!(tx.get_size(), len);
+        assert!(Transaction::deserialize(&bytes[..len]).unwrap() == (len, tx));
+
+        // every cut off tx has to be rejected instead of panicking
+        for end in 0..len {
+            assert!(Transaction::deserialize(&bytes[..end]).is_err());
+        }
     }
 
-    fn deserialize(bytes: &[u8]) -> (usize, Self) {
-        let mut start: usize = 0;
-        let (size, sign_len) = usize::deserialize(bytes);
-        start += size;
+    #[test]
+    fn socket_addrs() {
+        let mut bytes = [0u8; 32];
+        for addr in ["10.0.0.2:6969", "[fd00::2]:6970"] {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            let len = addr.serialize(&mut bytes);
+            assert_eq!(addr.get_size(), len);
+            assert_eq!(SocketAddr::deserialize(&bytes[..len]), Ok((len, addr)));
+        }
+
+        assert_eq!(SocketAddr::deserialize(&[5, 0, 0]), Err(DecodeError::Invalid("unknown ip version")));
+    }
+
+    #[derive(Serializer, Debug, PartialEq)]
+    #[serializer(crate = "super")]
+    enum Kind { A, B = 7, C }
+
+    #[derive(Serializer, Debug, PartialEq)]
+    struct Msg {
+        kind: Kind,
+        items: Vec<(u16, String)>,
+        parent: Option<[u8; 4]>,
+    }
+
+    #[test]
+    fn derived() {
+        let msg = Msg { kind: Kind::B, items: vec![(1, "a".to_string()), (2, "bc".to_string())], parent: Some([1, 2, 3, 4]) };
+
+        let mut bytes = [0u8; 64];
+        let len = msg.serialize(&mut bytes);
+        assert_eq!(len, 1 + 4 + (2 + 4 + 1) + (2 + 4 + 2) + 1 + 4);
+        assert_eq!(Msg::deserialize(&bytes[..len]), Ok((len, msg)));
 
-        return (start+sign_len, Signature::try_from(&bytes[start..start+sign_len]).unwrap());
+        // variants are encoded by their discriminant
+        assert_eq!(Kind::deserialize(&[8]), Ok((1, Kind::C)));
+        assert_eq!(Kind::deserialize(&[2]), Err(DecodeError::Invalid("unknown Kind variant")));
+        assert_eq!(Msg::deserialize(&bytes[..len - 1]), Err(DecodeError::UnexpectedEnd));
     }
 }
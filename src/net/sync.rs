@@ -0,0 +1,143 @@
+use crate::{blockchain::{Block, Blockchain, BlockHeader, TxProof}, crypto::Hash};
+
+use super::serialize::Serializer;
+
+/// max blocks per `BlocksRes` (less if they do not fit into one package)
+pub const BLOCKS_BATCH: usize = 16;
+/// max headers per `HeadersRes` (less if they do not fit into one package)
+pub const HEADERS_BATCH: usize = 2000;
+/// max proofs per `ProofsRes` (less if they do not fit into one package)
+pub const PROOFS_BATCH: usize = 256;
+/// upper bound of the size of everything in a response next to its list
+const RES_OVERHEAD: usize = 128;
+
+/// main chain tip of a node (answer to a `TipReq`, the blocks are asked for over the same connection)
+#[derive(Serializer)]
+pub struct Tip {
+    /// number of blocks in the main chain
+    pub height: usize,
+    pub hash: Hash,
+}
+
+impl Tip {
+    pub fn new(blockchain: &Blockchain) -> Tip {
+        return Tip { height: blockchain.get_round(), hash: blockchain.get_cur_hash() };
+    }
+}
+
+/// asks for the main chain blocks (or headers) starting at round `from` (the response comes over the same connection)
+#[derive(Serializer)]
+pub struct BlocksReq {
+    pub from: usize,
+}
+
+#[derive(Serializer)]
+pub struct BlocksRes {
+    pub tip: Tip,
+    pub from: usize,
+    pub blocks: Vec<Block>,
+}
+
+impl BlocksRes {
+    /// at most `max_size` bytes of blocks
+    pub fn new(tip: Tip, from: usize, blockchain: &Blockchain, max_size: usize) -> BlocksRes {
+        let blocks = fit(blockchain.get_blocks(from, BLOCKS_BATCH), max_size);
+        return BlocksRes { tip, from, blocks };
+    }
+
+    /// next request after the blocks were added
+    pub fn next_req(&self, contains: impl Fn(&Hash) -> bool) -> Option<BlocksReq> {
+        let links = self.blocks.iter().map(|block| (block.prev_hash, block.hash)).collect::<Vec<_>>();
+        let from = next_from(&self.tip, self.from, &links, BLOCKS_BATCH, contains)?;
+        return Some(BlocksReq { from });
+    }
+}
+
+#[derive(Serializer)]
+pub struct HeadersRes {
+    pub tip: Tip,
+    pub from: usize,
+    pub headers: Vec<BlockHeader>,
+}
+
+impl HeadersRes {
+    /// at most `max_size` bytes of headers
+    pub fn new(tip: Tip, from: usize, blockchain: &Blockchain, max_size: usize) -> HeadersRes {
+        let headers = fit(blockchain.get_blocks(from, HEADERS_BATCH).iter().map(|block| block.get_header()).collect(), max_size);
+        return HeadersRes { tip, from, headers };
+    }
+
+    /// next request after the headers were added
+    pub fn next_req(&self, contains: impl Fn(&Hash) -> bool) -> Option<BlocksReq> {
+        let links = self.headers.iter().map(|header| (header.prev_hash, header.hash)).collect::<Vec<_>>();
+        let from = next_from(&self.tip, self.from, &links, HEADERS_BATCH, contains)?;
+        return Some(BlocksReq { from });
+    }
+}
+
+/// asks for the proofs of the txs paying or paid by `pub_key` (in the order of the main chain),
+/// starting at the `from`th one
+#[derive(Serializer)]
+pub struct ProofsReq {
+    pub pub_key: String,
+    pub from: usize,
+}
+
+#[derive(Serializer)]
+pub struct ProofsRes {
+    pub from: usize,
+    pub proofs: Vec<TxProof>,
+}
+
+impl ProofsRes {
+    /// at most `max_size` bytes of proofs
+    pub fn new(req: &ProofsReq, blockchain: &Blockchain, max_size: usize) -> ProofsRes {
+        let proofs = fit(blockchain.get_proofs_of(&req.pub_key, req.from, PROOFS_BATCH), max_size);
+        return ProofsRes { from: req.from, proofs };
+    }
+
+    /// request of the next page (None once a page came back empty)
+    pub fn next_req(&self, pub_key: &str) -> Option<ProofsReq> {
+        if self.proofs.is_empty() {
+            return None;
+        }
+
+        return Some(ProofsReq { pub_key: pub_key.to_string(), from: self.from + self.proofs.len() });
+    }
+}
+
+/// as many items as fit into a response of at most `max_size` bytes
+fn fit<T: Serializer>(items: Vec<T>, max_size: usize) -> Vec<T> {
+    let mut size = RES_OVERHEAD;
+
+    let mut fitting = Vec::<T>::new();
+    for item in items {
+        size += item.get_size();
+        if size > max_size {
+            break;
+        }
+        fitting.push(item);
+    }
+
+    return fitting;
+}
+
+/// round to continue syncing from (None once synced or the peer has nothing useful)
+/// `links` are the prev hash and hash of every received block, `contains` tells if a hash is known by now
+fn next_from(tip: &Tip, from: usize, links: &[(Hash, Hash)], batch: usize, contains: impl Fn(&Hash) -> bool) -> Option<usize> {
+    let (first, last) = (links.first()?, links.last()?);
+    if contains(&tip.hash) {
+        return None;
+    }
+
+    // our chain forks off before `from`, so the first block has no parent yet
+    if !contains(&first.0) && from > 0 {
+        return Some(from.saturating_sub(batch));
+    }
+
+    if !contains(&last.1) {
+        return None;
+    }
+
+    return Some(from + links.len());
+}
@@ -1,10 +1,17 @@
 use std::{
-    net::{TcpListener, TcpStream, SocketAddr, Ipv4Addr, IpAddr},
-    io::{Read, Write},
-    sync::atomic::{AtomicU16, Ordering::Relaxed, AtomicUsize}
+    net::{TcpListener, TcpStream, SocketAddr},
+    io::{self, Read, Write},
+    sync::atomic::{Ordering::Relaxed, AtomicUsize}
 };
 
-use super::pkg::{Package, PKG_SIZE};
+use crate::crypto::Hash;
+
+use super::{pkg::{Package, PackageType}, serialize::{Serializer, to_bytes}};
+
+/// "GREY" (little endian), every chain spec has its own magic
+pub const DEFAULT_MAGIC: u32 = u32::from_le_bytes(*b"GREY");
+/// magic | package type | payload length | checksum (first 4 bytes of the sha256 of the payload)
+const HEADER_SIZE: usize = 4 + 1 + 4 + 4;
 
 static SEND_PKGS: AtomicUsize = AtomicUsize::new(0);
 
@@ -16,35 +23,160 @@ pub fn inc_pkgs_send() -> usize {
     return SEND_PKGS.fetch_add(1, Relaxed);
 }
 
-fn get_next_port() -> u16 {
-    static NEXT_PORT: AtomicU16 = AtomicU16::new(6969);
-    return NEXT_PORT.fetch_add(1, Relaxed);
-}
+/// listens on `addr` (v4 or v6, port 0 lets the os pick a free port) and returns the address it is bound to.
+/// The ip has to be reachable by the peers, since the returned address is the one announced to them
+/// (so no unspecified address like 0.0.0.0)
+pub fn init_receiver(addr: SocketAddr) -> io::Result<(SocketAddr, TcpListener)> {
+    if addr.ip().is_unspecified() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "can not announce an unspecified address"));
+    }
 
-pub fn init_receiver() -> Option<(u16, TcpListener)> {
-    let port = get_next_port();
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
-    let listener = TcpListener::bind(addr).unwrap();
-    listener.set_nonblocking(true).unwrap();
-    // TODO: handle error (if port in use next port)
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
 
-    return Some((port, listener));
+    return Ok((listener.local_addr()?, listener));
 }
 
-pub fn recv(mut stream: TcpStream) -> Option<Package> {
-    let mut buf = [0; PKG_SIZE];
-    stream.read_exact(&mut buf).unwrap();
+/// reads the next frame of a persistent stream (payloads bigger than `max_pkg_size` are rejected before allocating)
+/// frames of other chains (another `magic`) close the stream, invalid and unknown packages are skipped (`Ok(None)`)
+pub fn recv(stream: &mut TcpStream, max_pkg_size: usize, magic: u32) -> Result<Option<Package>, &'static str> {
+    let mut header = [0u8; HEADER_SIZE];
+    if stream.read_exact(&mut header).is_err() {
+        return Err("connection closed");
+    }
+
+    if u32::from_le_bytes(header[..4].try_into().unwrap()) != magic {
+        return Err("invalid magic number");
+    }
+
+    let len = u32::from_le_bytes(header[5..9].try_into().unwrap()) as usize;
+    if len > max_pkg_size {
+        return Err("message is too big");
+    }
+
+    let mut payload = vec![0u8; len];
+    if stream.read_exact(&mut payload).is_err() {
+        return Err("connection closed while reading a message");
+    }
+
+    let Ok((_, typ)) = PackageType::deserialize(&header[4..5]) else {
+        // not an error, the peer may speak a newer protocol version
+        println!("skip package of unknown type {}", header[4]);
+        return Ok(None);
+    };
+
+    if header[9..] != gen_checksum(&payload) {
+        eprintln!("ERROR: message checksum does not match");
+        return Ok(None);
+    }
+
+    let pkg = match Package::deserialize(typ, &payload) {
+        Ok(pkg) => pkg,
+        Err(err) => {
+            eprintln!("ERROR: could not decode package: {}", err);
+            return Ok(None);
+        }
+    };
 
-    let pkg = Package::deserialize(buf);
     if !pkg.verify() {
         eprintln!("ERROR: package is corrupted");
-        return None;
+        return Ok(None);
     }
 
-    return Some(pkg);
+    return Ok(Some(pkg));
 }
 
-pub fn send(mut stream: TcpStream, pkg: Package) {
+/// writes one frame (the stream stays open for the next ones), packages bigger than `max_pkg_size` are refused
+/// (the receivers of the chain use the same limit)
+pub fn send(stream: &mut TcpStream, pkg: &Package, max_pkg_size: usize, magic: u32) -> io::Result<()> {
+    if pkg.get_size() > max_pkg_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "package is too big"));
+    }
+
     inc_pkgs_send();
-    stream.write(&pkg.serialize()).unwrap();
+    return stream.write_all(&gen_frame(pkg, magic));
+}
+
+fn gen_frame(pkg: &Package, magic: u32) -> Vec<u8> {
+    let payload = pkg.serialize();
+
+    let mut frame = Vec::<u8>::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&magic.to_le_bytes());
+    frame.extend_from_slice(&to_bytes(&pkg.typ));
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&gen_checksum(&payload));
+    frame.extend_from_slice(&payload);
+
+    return frame;
+}
+
+fn gen_checksum(payload: &[u8]) -> [u8; 4] {
+    return Hash::digest(payload).0[..4].try_into().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{TcpListener, TcpStream}, io::Write, thread};
+
+    use crate::{test_utils::create_signer, net::{pkg::{Package, PackageType}, node::Node}};
+
+    use crate::net::pkg::DEFAULT_MAX_PKG_SIZE as MAX_PKG_SIZE;
+
+    use super::{recv, send, gen_frame, DEFAULT_MAGIC};
+
+    fn transfer(bytes: Vec<u8>, max_pkg_size: usize) -> Option<Package> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let sender = thread::spawn(move || { TcpStream::connect(addr).unwrap().write_all(&bytes).unwrap(); });
+        let pkg = recv(&mut listener.accept().unwrap().0, max_pkg_size, DEFAULT_MAGIC);
+        sender.join().unwrap();
+
+        return pkg.ok().flatten();
+    }
+
+    #[test]
+    fn frames() {
+        let (pub_key_pem, sign_key) = create_signer();
+        let node = Node { pub_key: pub_key_pem.clone(), addr: "[::1]:42".parse().unwrap(), online: true };
+        let pkg = Package::new(node, PackageType::Status, pub_key_pem.clone(), sign_key.clone());
+        let frame = gen_frame(&pkg, DEFAULT_MAGIC);
+
+        // small contents only need small frames
+        assert!(frame.len() < 2000);
+        assert!(transfer(frame.clone(), MAX_PKG_SIZE).is_some());
+
+        assert!(transfer(frame.clone(), 10).is_none());
+
+        // frames of another chain
+        assert!(transfer(gen_frame(&pkg, u32::from_le_bytes(*b"TEST")), MAX_PKG_SIZE).is_none());
+
+        let mut bad_payload = frame.clone();
+        *bad_payload.last_mut().unwrap() ^= 0xff;
+        assert!(transfer(bad_payload, MAX_PKG_SIZE).is_none());
+
+        // frames of unknown types are skipped without losing the next frame of the stream
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut unknown_type = frame.clone();
+        unknown_type[4] = u8::MAX;
+        stream.write_all(&unknown_type).unwrap();
+        send(&mut stream, &pkg, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap();
+        drop(stream);
+
+        let mut stream = listener.accept().unwrap().0;
+        assert!(recv(&mut stream, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap().is_none());
+        assert!(recv(&mut stream, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap().is_some());
+        assert!(recv(&mut stream, MAX_PKG_SIZE, DEFAULT_MAGIC).is_err());
+
+        // too big packages are refused before anything is written
+        let too_big = Package::new(vec![0u8; MAX_PKG_SIZE], PackageType::Status, pub_key_pem, sign_key);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert_eq!(send(&mut stream, &too_big, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        send(&mut stream, &pkg, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap();
+        drop(stream);
+
+        assert!(recv(&mut listener.accept().unwrap().0, MAX_PKG_SIZE, DEFAULT_MAGIC).unwrap().is_some());
+    }
 }
@@ -0,0 +1,16 @@
+use rsa::{pss::BlindedSigningKey, sha2::Sha256, pkcs8::EncodePublicKey};
+
+use crate::{blockchain::ProofOfWork, crypto::{create_key_pair, Hash}};
+
+/// pem of the pub key (the address in txs) and the signing key of a new key pair
+pub fn create_signer() -> (String, BlindedSigningKey<Sha256>) {
+    let (pub_key, priv_key) = create_key_pair();
+    let pub_key_pem = pub_key.to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap();
+    return (pub_key_pem, BlindedSigningKey::<Sha256>::from(priv_key));
+}
+
+/// seal of the first proof of work solution for the header below `target`
+pub fn solve(header_hash: &Hash, target: &Hash) -> Vec<u8> {
+    let solution = (0..).find(|s| ProofOfWork::gen_mining_hash(header_hash, *s) < *target).unwrap();
+    return solution.to_le_bytes().to_vec();
+}
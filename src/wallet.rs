@@ -1,27 +1,53 @@
 use std::{
-    net::{TcpStream, SocketAddr, Ipv4Addr, IpAddr, TcpListener},
+    net::{SocketAddr, TcpListener},
     thread::{JoinHandle, self},
     time::Duration,
-    sync::{Arc, Mutex}, fs
+    sync::{Arc, Mutex}, fs,
+    path::{Path, PathBuf}
 };
 
 use crate::{
     net::{
-        tcp::{init_receiver, recv, send},
+        tcp::init_receiver,
         pkg::{Package, PackageType},
-        network::Network, serialize::Serializer, node::Node
+        network::Network, serialize::DecodeError, node::Node, handshake::{Version, FEATURE_FULL_NODE}, sync::{Tip, BlocksReq, BlocksRes, HeadersRes, ProofsReq, ProofsRes}
     },
-    blockchain::{Blockchain, Transaction, Block, Miner},
-    crypto::create_key_pair
+    blockchain::{Blockchain, Transaction, Block, DoubleSign, Miner, MinerStats, MerkleProof, Amount, ChainError, LightClient, ChainSpec},
+    crypto::{create_key_pair, Hash}
 };
 
 use rsa::{RsaPrivateKey, RsaPublicKey, sha2::Sha256, pss::BlindedSigningKey, pkcs8::EncodePublicKey};
 
-const TIMEOUT: Duration = Duration::from_secs(5);
-const BLOCKCHAINS_DIR: &str = "blockchains";
+/// how long the receiving thread waits for a package before it looks at the listener and the miner again
+const RECV_SLEEP: Duration = Duration::from_millis(10);
+const JOIN_SLEEP: Duration = Duration::from_millis(100);
+/// how long to wait for the first handshake with a master node
+const JOIN_TIMEOUT: Duration = Duration::from_secs(7);
+/// fee rate estimates are based on the txs of that many recent blocks
+const FEE_ESTIMATE_BLOCKS: usize = 10;
+/// fee rate (units per 1000 bytes) if there are no recent txs
+const DEFAULT_FEE_RATE: u64 = 1000;
+/// mining threads per wallet if the config does not say otherwise (the examples run many wallets on one machine)
+const DEFAULT_MINER_THREADS: usize = 1;
+
+/// settings of the local node (unlike the chain spec they may differ between the nodes)
+#[derive(Clone, Debug)]
+pub struct WalletConfig {
+    /// has to be reachable by the other nodes (see `init_receiver`, port 0 picks a free port)
+    pub bind_addr: SocketAddr,
+    /// the blocks are stored in it (see `gen_store_path`), light wallets keep no blocks
+    pub data_dir: PathBuf,
+    pub miner_threads: usize,
+}
+
+impl WalletConfig {
+    pub fn new(bind_addr: SocketAddr, data_dir: &Path) -> WalletConfig {
+        return WalletConfig { bind_addr, data_dir: data_dir.to_path_buf(), miner_threads: DEFAULT_MINER_THREADS };
+    }
+}
 
 pub struct Wallet {
-    pub port: u16,
+    pub addr: SocketAddr,
 
     pub pub_key_pem: String,
     pub_key: RsaPublicKey,
@@ -29,91 +55,189 @@ pub struct Wallet {
     sign_key: BlindedSigningKey<Sha256>,
 
     blockchain: Arc<Mutex<Blockchain>>,
+    miner: Arc<Mutex<Miner>>,
+    /// only for light wallets (their `blockchain` stays empty)
+    light: Option<Arc<Mutex<LightClient>>>,
+    /// seq for the next tx (the pending txs are not in the chain yet)
+    next_seq: Mutex<u64>,
     online: Arc<Mutex<bool>>,
     idling: Arc<Mutex<bool>>,
     recv_thread: JoinHandle<()>,
     network: Arc<Mutex<Network>>,
+    spec: ChainSpec,
 }
 
 impl Wallet {
-    pub fn new(master_nodes: &Vec<Node>) -> Wallet {
-        let (pub_key, priv_key) = create_key_pair();
-        let pub_key_pem = pub_key.to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap();
-        let sign_key = BlindedSigningKey::<Sha256>::from(priv_key.clone());
-
-        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+    pub fn new(config: &WalletConfig, master_nodes: &[Node], spec: &ChainSpec) -> Result<Wallet, &'static str> {
+        return Self::with_key(create_key_pair().1, config, master_nodes, spec);
+    }
 
-        let online = Arc::new(Mutex::new(true));
-        let idling = Arc::new(Mutex::new(false));
-        let (port, listener) = init_receiver().expect("ERROR: could not create socket");
+    /// wallet which keeps only the headers and the proven txs of its key (and does not mine),
+    /// not for proof of stake chains (the proposers can not be checked without the stakes)
+    pub fn new_light(config: &WalletConfig, master_nodes: &[Node], spec: &ChainSpec) -> Result<Wallet, &'static str> {
+        if !spec.create_consensus().checks_headers() {
+            return Err("light wallets can not follow chains of this consensus");
+        }
 
-        let network = Arc::new(Mutex::new(Network::new(master_nodes)));
-        let recv_thread = recv_loop(
-            pub_key_pem.clone(),
-            sign_key.clone(), 
-            Arc::clone(&online),
-            Arc::clone(&idling),
-            listener,
-            Arc::clone(&blockchain),
-            Arc::clone(&network)
-        );
+        let wallet = Self::create(create_key_pair().1, config, Network::new(master_nodes, spec, 0), true, spec)?;
+        wallet.join_network();
+        return Ok(wallet);
+    }
 
-        println!("created new wallet at port {}", port);
+    pub fn new_master_node(config: &WalletConfig, spec: &ChainSpec) -> Result<Wallet, &'static str> {
+        return Self::with_key(create_key_pair().1, config, &Vec::new(), spec);
+    }
 
-        network.lock().unwrap()
-            .go_online(pub_key_pem.clone(), port, sign_key.clone())
-            .expect("ERROR: could not init network (no response)");
+    /// wallet of a known key (e.g. an authority of the consensus), a master node if there are no `master_nodes`
+    /// (it continues the blockchain the key stored in the data dir before)
+    pub fn with_key(priv_key: RsaPrivateKey, config: &WalletConfig, master_nodes: &[Node], spec: &ChainSpec) -> Result<Wallet, &'static str> {
+        if master_nodes.is_empty() {
+            return Self::create(priv_key, config, Network::new_empty(spec, FEATURE_FULL_NODE), false, spec);
+        }
 
-        return Wallet{ port, online, idling, recv_thread, priv_key, pub_key, blockchain, network, pub_key_pem, sign_key };
+        let wallet = Self::create(priv_key, config, Network::new(master_nodes, spec, FEATURE_FULL_NODE), false, spec)?;
+        wallet.join_network();
+        return Ok(wallet);
     }
 
-    pub fn new_master_node() -> Wallet {
-        let (pub_key, priv_key) = create_key_pair();
+    /// light wallets do not use the data dir (they keep no blocks)
+    fn create(priv_key: RsaPrivateKey, config: &WalletConfig, network: Network, light: bool, spec: &ChainSpec) -> Result<Wallet, &'static str> {
+        let pub_key = RsaPublicKey::from(&priv_key);
         let pub_key_pem = pub_key.to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap();
         let sign_key = BlindedSigningKey::<Sha256>::from(priv_key.clone());
 
-        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+        let (addr, listener) = match init_receiver(config.bind_addr) {
+            Ok(receiver) => receiver,
+            Err(err) => {
+                eprintln!("ERROR: could not listen on {}: {}", config.bind_addr, err);
+                return Err("could not listen on the bind address");
+            }
+        };
+
+        let (blockchain, light) = if light {
+            let light = LightClient::new(pub_key_pem.clone(), spec);
+            let blockchain = Blockchain::new(spec);
+            (Arc::new(Mutex::new(blockchain)), Some(Arc::new(Mutex::new(light))))
+        } else {
+            (Arc::new(Mutex::new(load_blockchain(&config.data_dir, &pub_key_pem, spec))), None)
+        };
+        let miner = Arc::new(Mutex::new(Miner::new(pub_key_pem.clone(), sign_key.clone(), config.miner_threads)));
 
         let online = Arc::new(Mutex::new(true));
         let idling = Arc::new(Mutex::new(false));
-        let (port, listener) = init_receiver().expect("ERROR: could not create socket");
 
-        let network = Arc::new(Mutex::new(Network::new_empty()));
+        let network = Arc::new(Mutex::new(network));
         let recv_thread = recv_loop(
             pub_key_pem.clone(),
             sign_key.clone(), 
+            addr,
             Arc::clone(&online),
             Arc::clone(&idling),
             listener,
             Arc::clone(&blockchain),
-            Arc::clone(&network)
+            Arc::clone(&miner),
+            Arc::clone(&network),
+            light.clone()
         );
 
-        println!("created new wallet at port {}", port);
-        return Wallet{ port, online, idling, recv_thread, priv_key, pub_key, blockchain, network, pub_key_pem, sign_key };
+        println!("created new {}wallet at {}", if light.is_some() { "light " } else { "" }, addr);
+        return Ok(Wallet{ addr, online, idling, recv_thread, priv_key, pub_key, blockchain, miner, light, next_seq: Mutex::new(0), network, spec: spec.clone(), pub_key_pem, sign_key });
+    }
+
+    /// starts the handshakes with the master nodes and waits for the first peer
+    /// (syncing the blockchain or the headers starts with the versions of the peers)
+    fn join_network(&self) {
+        let height = get_height(&self.blockchain, &self.light);
+        self.network.lock().unwrap().go_online(self.pub_key_pem.clone(), self.addr, self.sign_key.clone(), height);
+
+        for _ in 0..JOIN_TIMEOUT.as_millis() / JOIN_SLEEP.as_millis() {
+            if self.network.lock().unwrap().get_len() > 0 {
+                return;
+            }
+            thread::sleep(JOIN_SLEEP);
+        }
+
+        eprintln!("ERROR: could not join the network (no compatible master node answered)");
+    }
+
+    /// returns the id of the tx
+    pub fn send_tx(&self, payee: &String, amount: Amount, fee: Amount) -> Hash {
+        return self.send(Transaction::new(&self.spec.gen_id(), &self.pub_key_pem, payee, self.gen_seq(), amount, fee, &self.sign_key));
     }
 
-    pub fn send_tx(&self, payee: &String, amount: f64) {
-        let tx = Transaction::new(&self.pub_key_pem, payee, amount);
+    /// locks `amount` of the balance to become a validator (for proof of stake), returns the id of the tx
+    pub fn send_stake(&self, amount: Amount, fee: Amount) -> Hash {
+        return self.send(Transaction::new_stake(&self.spec.gen_id(), &self.pub_key_pem, self.gen_seq(), amount, fee, &self.sign_key));
+    }
+
+    fn send(&self, tx: Transaction) -> Hash {
+        let id = tx.gen_hash();
         let sender = tx.payer.clone();
+
+        // the own miner does not get the broadcast (but may be the one sealing the next block)
+        if self.light.is_none() {
+            *self.idling.lock().unwrap() = false;
+            let blockchain = self.blockchain.lock().unwrap();
+            self.miner.lock().unwrap().add_tx(tx.clone(), &blockchain);
+        }
+
         let pkg = Package::new(tx, PackageType::Tx, sender, self.sign_key.clone());
 
         self.network.lock().unwrap().broadcast(pkg);
+        return id;
+    }
+
+    /// higher than the seqs of the txs in the chain and of the txs sent before
+    fn gen_seq(&self) -> u64 {
+        let chain_seq = match &self.light {
+            Some(light) => light.lock().unwrap().get_next_seq(),
+            None => self.blockchain.lock().unwrap().get_next_seq(&self.pub_key_pem),
+        };
+
+        let mut next_seq = self.next_seq.lock().unwrap();
+        let seq = chain_seq.max(*next_seq);
+        *next_seq = seq.saturating_add(1);
+        return seq;
+    }
+
+    /// fee of a tx to `payee` paying the median fee rate of the recent blocks
+    pub fn estimate_fee(&self, payee: &str) -> Amount {
+        let fee_rate = self.blockchain.lock().unwrap().get_median_fee_rate(FEE_ESTIMATE_BLOCKS).unwrap_or(DEFAULT_FEE_RATE);
+        let size = Transaction::gen_size(&self.pub_key_pem, payee) as u128;
+        return Amount::from_units((fee_rate as u128 * size).div_ceil(1000).min(u64::MAX as u128) as u64);
+    }
+
+    /// mines a block without tx to earn the block reward
+    pub fn mine_reward(&self) {
+        *self.idling.lock().unwrap() = false;
+
+        let blockchain = self.blockchain.lock().unwrap();
+        self.miner.lock().unwrap().mine_reward(&blockchain);
     }
 
     pub fn is_idling(&self) -> bool {
         return *self.idling.lock().unwrap();
     }
 
+    pub fn get_spec(&self) -> &ChainSpec {
+        return &self.spec;
+    }
+
+    pub fn get_mining_stats(&self) -> MinerStats {
+        return self.miner.lock().unwrap().get_stats();
+    }
+
     pub fn shutdown(self) {
         let pub_key_pem = self.pub_key.to_public_key_pem(rsa::pkcs8::LineEnding::LF).unwrap();
         let sign_key = BlindedSigningKey::<Sha256>::from(self.priv_key.clone());
 
-        self.network.lock().unwrap().go_offline(pub_key_pem, self.port, sign_key);
+        self.network.lock().unwrap().go_offline(pub_key_pem, self.addr, sign_key);
         *self.online.lock().unwrap() = false;
         self.recv_thread.join().unwrap();
 
-        save_blockchain(&self.blockchain.lock().unwrap(), &format!("wallet{}", self.port));
+        if let Ok(miner) = Arc::try_unwrap(self.miner) {
+            miner.into_inner().unwrap().shutdown();
+        }
 
         println!("wallet is offline now");
     }
@@ -126,20 +250,85 @@ impl Wallet {
         return self.network.lock().unwrap().get_len();
     }
 
-    pub fn get_cur_hash(&self) -> u64 {
+    pub fn get_cur_hash(&self) -> Hash {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_headers().get_cur_hash();
+        }
+
         return self.blockchain.lock().unwrap().get_cur_hash();
     }
 
-    pub fn get_tx_ids(&self) -> Vec<u64> {
+    /// light wallets only know their proven txs
+    pub fn get_tx_ids(&self) -> Vec<Hash> {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_tx_ids();
+        }
+
         return self.blockchain.lock().unwrap().get_tx_ids();
     }
 
-    pub fn get_blockchain_hashes(&self) -> Vec<(u64, u64)> {
+    pub fn get_tx(&self, tx_id: Hash) -> Option<Transaction> {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_proof(&tx_id).map(|proof| proof.tx.clone());
+        }
+
+        return self.blockchain.lock().unwrap().get_tx(tx_id).cloned();
+    }
+
+    pub fn get_merkle_proof(&self, tx_id: Hash) -> Option<(Hash, MerkleProof)> {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_proof(&tx_id).map(|proof| (proof.block_hash, proof.proof.clone()));
+        }
+
+        return self.blockchain.lock().unwrap().get_merkle_proof(tx_id);
+    }
+
+    /// light wallets check the proof against their headers
+    pub fn verify_merkle_proof(&self, block_hash: &Hash, tx: &Transaction, proof: &MerkleProof) -> bool {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_headers().verify_merkle_proof(block_hash, tx, proof);
+        }
+
+        return self.blockchain.lock().unwrap().verify_merkle_proof(block_hash, tx, proof);
+    }
+
+    /// light wallets only know the balance from their allocation and their proven txs
+    pub fn get_balance(&self) -> Amount {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_balance();
+        }
+
+        return self.get_balance_of(&self.pub_key_pem);
+    }
+
+    /// light wallets only know their own balance (other keys have none as far as they can tell)
+    pub fn get_balance_of(&self, pub_key: &str) -> Amount {
+        if let Some(light) = &self.light {
+            return if pub_key == self.pub_key_pem { light.lock().unwrap().get_balance() } else { Amount::ZERO };
+        }
+
+        return self.blockchain.lock().unwrap().balance_of(pub_key);
+    }
+
+    /// headers of light wallets are checked when they are added (there are no blocks to validate)
+    pub fn verify_chain(&self) -> Result<(), ChainError> {
+        if self.light.is_some() {
+            return Ok(());
+        }
+
+        return self.blockchain.lock().unwrap().validate();
+    }
+
+    pub fn get_blockchain_hashes(&self) -> Vec<(Hash, Hash)> {
+        if let Some(light) = &self.light {
+            return light.lock().unwrap().get_headers().get_hashes();
+        }
+
         return self.blockchain.lock().unwrap().get_hashes();
     }
 
     pub fn get_name(&self) -> String {
-        return format!("wallet{}", self.port);
+        return gen_name(self.addr);
     }
 }
 
@@ -149,106 +338,327 @@ impl PartialEq for Wallet {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn recv_loop(pub_key: String, sign_key: BlindedSigningKey::<Sha256>, 
+             addr: SocketAddr,
              online: Arc<Mutex<bool>>,
              idling: Arc<Mutex<bool>>,
              listener: TcpListener,
              blockchain: Arc<Mutex<Blockchain>>,
-             network: Arc<Mutex<Network>>) -> JoinHandle<()> {
+             miner: Arc<Mutex<Miner>>,
+             network: Arc<Mutex<Network>>,
+             light: Option<Arc<Mutex<LightClient>>>) -> JoinHandle<()> {
 
     return thread::spawn(move || {
-        let mut miner = Miner::new();
-
+        let inbound = network.lock().unwrap().take_receiver().expect("only one receiving thread per network");
         while *online.lock().unwrap() {
-            let stream = listener.accept();
+            // the connections stay open, their readers pass the packages on
+            if let Ok((stream, _)) = listener.accept() {
+                network.lock().unwrap().accept(stream);
+            }
 
-            if let Ok((stream, _)) = stream {
+            if let Ok((conn, pkg)) = inbound.recv_timeout(RECV_SLEEP) {
                 *idling.lock().unwrap() = false;
-                if let Some(pkg) = recv(stream) {
-                    handle_pkg(&pub_key, &sign_key, pkg, &blockchain, &network, &mut miner);
+                // everything but the version waits for the handshake
+                let is_peer = matches!(pkg.typ, PackageType::Version) || network.lock().unwrap().check_peer(&pkg.sender, &conn);
+                if !is_peer {
+                    println!("drop {:?} package of a node without handshake", pkg.typ);
+                } else if let Err(err) = handle_pkg(&pub_key, &sign_key, addr, conn, pkg, &blockchain, &network, &miner, &light) {
+                    eprintln!("ERROR: dropped package with invalid content: {}", err);
                 }
-            } else if miner.is_idling() {
+            } else if miner.lock().unwrap().is_idling() {
                 *idling.lock().unwrap() = true;
             }
 
-            if let Some((tx, solution, round)) = miner.recv_solution() {
-                let nonce = tx.gen_nonce();
-                let prev_hash = blockchain.lock().unwrap().get_prev_hash(round);
-
-                let block = Block::new(tx, prev_hash, round, nonce, solution);
+            let block = miner.lock().unwrap().recv_solution();
+            if let Some(block) = block {
                 let pkg = Package::new(block, PackageType::Block, pub_key.to_string(), sign_key.to_owned());
-                handle_pkg(&pub_key, &sign_key, pkg.clone(), &blockchain, &network, &mut miner);
+                handle_pkg(&pub_key, &sign_key, addr, addr, pkg.clone(), &blockchain, &network, &miner, &light)
+                    .expect("own block has to be decodable");
                 network.lock().unwrap().broadcast(pkg);
             }
-        } 
 
-        miner.shutdown();
+            // peers which dropped chain data (only full nodes send it) get our tip instead
+            let stale = {
+                let mut network = network.lock().unwrap();
+                network.drop_silent();
+                network.take_stale()
+            };
+            if !stale.is_empty() && light.is_none() {
+                let tip = Tip::new(&blockchain.lock().unwrap());
+                let pkg = Package::new(tip, PackageType::TipRes, pub_key.to_string(), sign_key.to_owned());
+                let mut network = network.lock().unwrap();
+                for conn in stale {
+                    network.send_to(&conn, pkg.clone());
+                }
+            }
+        } 
     });
 }
 
-fn handle_pkg(pub_key: &String, sign_key: &BlindedSigningKey::<Sha256>, pkg: Package,
+/// `conn` is the connection the package came over (replies go there)
+#[allow(clippy::too_many_arguments)]
+fn handle_pkg(pub_key: &String, sign_key: &BlindedSigningKey::<Sha256>, addr: SocketAddr, conn: SocketAddr, pkg: Package,
               blockchain: &Arc<Mutex<Blockchain>>,
               network: &Arc<Mutex<Network>>,
-              miner: &mut Miner) {
+              miner: &Arc<Mutex<Miner>>,
+              light: &Option<Arc<Mutex<LightClient>>>) -> Result<(), DecodeError> {
+    if let Some(light) = light {
+        if !matches!(pkg.typ, PackageType::Version | PackageType::Verack | PackageType::Status | PackageType::NodesRes) {
+            return handle_light_pkg(pub_key, sign_key, conn, pkg, &mut light.lock().unwrap(), &mut network.lock().unwrap());
+        }
+    }
+
     match pkg.typ {
         PackageType::Tx => {
-            let tx = Transaction::deserialize(&pkg.content).1;
-            miner.add_tx(tx, blockchain.lock().unwrap().get_round());
+            let tx = pkg.decode::<Transaction>()?;
+            let blockchain = blockchain.lock().unwrap();
+            miner.lock().unwrap().add_tx(tx, &blockchain);
         }
 
-        PackageType::Status => {
-            let node = Node::deserialize(&pkg.content).1;
+        PackageType::Version => {
+            let version = pkg.decode::<Version>()?;
+            let height = get_height(blockchain, light);
 
             let network = &mut network.lock().unwrap();
-            if node.online {
-                if !pkg.is_forwarded {
-                    let nodes_pkg = Package::new(network.to_nodes(), PackageType::NodesRes,
-                        pub_key.to_string(), sign_key.to_owned());
-
-                    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), node.port);
-                    let client = TcpStream::connect_timeout(&addr, TIMEOUT);
-
-                    if let Ok(stream) = client {
-                        send(stream, nodes_pkg);
-                    } else {
-                        println!("could not connect to 127.0.0.1:{}", node.port);
-                    }
+            let greet = match network.add_peer(pkg.sender.clone(), &version, conn) {
+                Ok(greet) => greet,
+                Err(err) => {
+                    println!("disconnect {}: {}", version.addr, err);
+                    return Ok(());
                 }
+            };
 
-                if network.contains(&node.pub_key) {
-                    network.broadcast_forward(pkg);
-                    network.register(node.pub_key.clone(), node.port);
-                }
-            } else {
-                network.deregister(node.pub_key);
+            // our version has to arrive before the verack (veracks of unknown nodes are dropped)
+            if greet {
+                network.greet(&conn, pub_key.to_string(), addr, sign_key.to_owned(), height);
+                let nodes = network.to_nodes(&pkg.sender);
+                network.send_to(&conn, Package::new(nodes, PackageType::NodesRes, pub_key.to_string(), sign_key.to_owned()));
+            }
+            network.send_to(&conn, Package::new(version.nonce, PackageType::Verack, pub_key.to_string(), sign_key.to_owned()));
+
+            if version.height > height && version.has_feature(FEATURE_FULL_NODE) {
+                network.send_to(&conn, Package::new(height, PackageType::TipReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::Verack => {
+            let nonce = pkg.decode::<u64>()?;
+            network.lock().unwrap().ack(&pkg.sender, nonce);
+        }
+
+        PackageType::Status => {
+            let node = pkg.decode::<Node>()?;
+            if !node.online {
+                network.lock().unwrap().deregister(&pkg.sender);
             }
         }
 
         PackageType::NodesRes => {
-            let nodes = Vec::<Node>::deserialize(&pkg.content).1;
+            let nodes = pkg.decode::<Vec<Node>>()?;
+            let height = get_height(blockchain, light);
 
             let network = &mut network.lock().unwrap();
             for node in nodes {
-                network.register(node.pub_key, node.port);
+                if node.addr != addr && !network.knows(&node.pub_key, &node.addr) {
+                    network.greet(&node.addr, pub_key.to_string(), addr, sign_key.to_owned(), height);
+                }
             }
         }
 
         PackageType::Block => {
-            let block = Block::deserialize(&pkg.content).1;
+            let block = pkg.decode::<Block>()?;
             let blockchain = &mut blockchain.lock().unwrap();
-            blockchain.add_block(&block);
+            let evidence = add_blocks(std::slice::from_ref(&block), blockchain, &mut miner.lock().unwrap());
+            let mut network = network.lock().unwrap();
+            broadcast_evidence(pub_key, sign_key, evidence, &mut network);
+
+            // we missed a block before it (only asked once per gap, the parent of the next orphan is an orphan itself)
+            if blockchain.is_orphan(&block.hash) && !blockchain.is_orphan(&block.prev_hash) {
+                network.send_to(&conn, Package::new(blockchain.get_round(), PackageType::TipReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::Evidence => {
+            let evidence = pkg.decode::<DoubleSign>()?;
+            let blockchain = blockchain.lock().unwrap();
+            miner.lock().unwrap().add_evidence(evidence, &blockchain);
+        }
+
+        PackageType::TipReq => {
+            // the height of the requester
+            pkg.decode::<usize>()?;
+            let tip = Tip::new(&blockchain.lock().unwrap());
+            network.lock().unwrap().send_to(&conn, Package::new(tip, PackageType::TipRes, pub_key.to_string(), sign_key.to_owned()));
+        }
+
+        PackageType::TipRes => {
+            let tip = pkg.decode::<Tip>()?;
+            let blockchain = blockchain.lock().unwrap();
+            if !blockchain.contains(&tip.hash) {
+                let req = BlocksReq { from: blockchain.get_round().min(tip.height) };
+                network.lock().unwrap().send_to(&conn, Package::new(req, PackageType::BlocksReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::BlocksReq => {
+            let req = pkg.decode::<BlocksReq>()?;
+            let blockchain = blockchain.lock().unwrap();
+            let mut network = network.lock().unwrap();
+            let res = BlocksRes::new(Tip::new(&blockchain), req.from, &blockchain, network.get_max_content_size());
+            network.send_to(&conn, Package::new(res, PackageType::BlocksRes, pub_key.to_string(), sign_key.to_owned()));
+        }
+
+        PackageType::BlocksRes => {
+            let res = pkg.decode::<BlocksRes>()?;
+            let blockchain = &mut blockchain.lock().unwrap();
+            let evidence = add_blocks(&res.blocks, blockchain, &mut miner.lock().unwrap());
+            broadcast_evidence(pub_key, sign_key, evidence, &mut network.lock().unwrap());
+
+            if let Some(req) = res.next_req(|hash| blockchain.contains(hash)) {
+                network.lock().unwrap().send_to(&conn, Package::new(req, PackageType::BlocksReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::HeadersReq => {
+            let req = pkg.decode::<BlocksReq>()?;
+            let blockchain = blockchain.lock().unwrap();
+            let mut network = network.lock().unwrap();
+            let res = HeadersRes::new(Tip::new(&blockchain), req.from, &blockchain, network.get_max_content_size());
+            network.send_to(&conn, Package::new(res, PackageType::HeadersRes, pub_key.to_string(), sign_key.to_owned()));
+        }
+
+        PackageType::ProofsReq => {
+            let req = pkg.decode::<ProofsReq>()?;
+            let mut network = network.lock().unwrap();
+            let res = ProofsRes::new(&req, &blockchain.lock().unwrap(), network.get_max_content_size());
+            network.send_to(&conn, Package::new(res, PackageType::ProofsRes, pub_key.to_string(), sign_key.to_owned()));
+        }
+
+        // only light wallets ask for those
+        PackageType::HeadersRes | PackageType::ProofsRes => {}
+    }
+
+    return Ok(());
+}
+
+/// light wallets only follow the headers and the txs of their key
+fn handle_light_pkg(pub_key: &String, sign_key: &BlindedSigningKey::<Sha256>, conn: SocketAddr, pkg: Package,
+                    light: &mut LightClient, network: &mut Network) -> Result<(), DecodeError> {
+    match pkg.typ {
+        PackageType::Block => {
+            let block = pkg.decode::<Block>()?;
+            match light.add_block(&block) {
+                // we missed a header before it (asked once per gap like full nodes do)
+                Ok(false) if !light.get_headers().is_orphan(&block.prev_hash) => {
+                    let height = light.get_headers().get_round();
+                    network.send_to(&conn, Package::new(height, PackageType::TipReq, pub_key.to_string(), sign_key.to_owned()));
+                }
+                Ok(_) => {}
+                Err(err) => println!("discard block (round: {}): {}", block.round, err),
+            }
         }
+
+        PackageType::TipRes => {
+            let tip = pkg.decode::<Tip>()?;
+            let headers = light.get_headers();
+            if !headers.contains(&tip.hash) {
+                let req = BlocksReq { from: headers.get_round().min(tip.height) };
+                network.send_to(&conn, Package::new(req, PackageType::HeadersReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::HeadersRes => {
+            let res = pkg.decode::<HeadersRes>()?;
+            for header in &res.headers {
+                if let Err(err) = light.add_header(header.clone()) {
+                    println!("discard header (round: {}): {}", header.round, err);
+                }
+            }
+
+            let headers = light.get_headers();
+            if let Some(req) = res.next_req(|hash| headers.contains(hash)) {
+                network.send_to(&conn, Package::new(req, PackageType::HeadersReq, pub_key.to_string(), sign_key.to_owned()));
+            } else {
+                let req = ProofsReq { pub_key: pub_key.to_string(), from: 0 };
+                network.send_to(&conn, Package::new(req, PackageType::ProofsReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        PackageType::ProofsRes => {
+            let res = pkg.decode::<ProofsRes>()?;
+            for proof in &res.proofs {
+                if let Err(err) = light.add_proof(proof.clone()) {
+                    println!("discard tx proof: {}", err);
+                }
+            }
+
+            if let Some(req) = res.next_req(pub_key) {
+                network.send_to(&conn, Package::new(req, PackageType::ProofsReq, pub_key.to_string(), sign_key.to_owned()));
+            }
+        }
+
+        // light wallets do not mine and have no blocks to share
+        _ => {}
+    }
+
+    return Ok(());
+}
+
+/// blocks (or headers of light wallets) in the main chain
+fn get_height(blockchain: &Arc<Mutex<Blockchain>>, light: &Option<Arc<Mutex<LightClient>>>) -> usize {
+    if let Some(light) = light {
+        return light.lock().unwrap().get_headers().get_round();
+    }
+
+    return blockchain.lock().unwrap().get_round();
+}
+
+/// txs of blocks dropped by a reorg go back to the miner
+/// returns the double signs found among the blocks (they go to the miner as well)
+fn add_blocks(blocks: &[Block], blockchain: &mut Blockchain, miner: &mut Miner) -> Vec<DoubleSign> {
+    let mut found = Vec::<DoubleSign>::new();
+    for block in blocks {
+        for tx in blockchain.add_block(block) {
+            miner.add_tx(tx, blockchain);
+        }
+
+        if let Some(evidence) = blockchain.find_double_sign(block) {
+            println!("found double sign in round {}", block.round);
+            miner.add_evidence(evidence.clone(), blockchain);
+            found.push(evidence);
+        }
+    }
+    miner.update(blockchain);
+    return found;
+}
+
+/// lets the other nodes slash the validator as well (even if they saw only one of the blocks)
+fn broadcast_evidence(pub_key: &str, sign_key: &BlindedSigningKey::<Sha256>, evidence: Vec<DoubleSign>, network: &mut Network) {
+    for evidence in evidence {
+        network.broadcast(Package::new(evidence, PackageType::Evidence, pub_key.to_string(), sign_key.to_owned()));
     }
 }
 
-fn save_blockchain(blockchain: &Blockchain, name: &str) {
-    fs::create_dir_all(BLOCKCHAINS_DIR).unwrap();
+/// name of the wallet listening on `addr` (unique on the machine, also for different ips)
+fn gen_name(addr: SocketAddr) -> String {
+    return match addr {
+        SocketAddr::V4(addr) if addr.ip().is_loopback() => format!("wallet{}", addr.port()),
+        _ => format!("wallet-{}-{}", addr.ip(), addr.port()),
+    };
+}
 
-    let content = blockchain.to_string();
+/// file of the blocks of the key on the chain of the spec (so the same key continues its chain after a restart)
+fn gen_store_path(data_dir: &Path, pub_key: &str, spec: &ChainSpec) -> PathBuf {
+    let chain_id = spec.gen_id().to_string();
+    let key_id = Hash::digest(pub_key.as_bytes()).to_string();
+    return data_dir.join(format!("{}-{}.blocks", &chain_id[..16], &key_id[..16]));
+}
 
-    if let Err(err) = fs::write(BLOCKCHAINS_DIR.to_owned() + "/" + name, &content) {
-        println!("{}", err);
-    } else {
-        println!("saved blockchain to {}", name);
+fn load_blockchain(data_dir: &Path, pub_key: &str, spec: &ChainSpec) -> Blockchain {
+    if let Err(err) = fs::create_dir_all(data_dir) {
+        eprintln!("ERROR: could not create data dir {}: {}", data_dir.display(), err);
     }
+
+    return Blockchain::load(&gen_store_path(data_dir, pub_key, spec), spec);
 }